@@ -0,0 +1,68 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Time source of a `TaskLoop`. Times are measured from an arbitrary epoch.
+pub trait Clock {
+    fn now(&self) -> Duration;
+    // Lets `duration` pass on this clock.
+    fn sleep(&self, duration: Duration);
+    // How long the loop may block waiting for background updates while
+    // `duration` passes on this clock.
+    fn blocking(&self, duration: Duration) -> Duration {
+        duration
+    }
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock { start: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+// Virtual clock that only moves when slept on or advanced. Clones share the
+// same time.
+#[derive(Clone, Default)]
+pub struct MockClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock::default()
+    }
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+    fn blocking(&self, _: Duration) -> Duration {
+        Duration::from_secs(0)
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::{FrameId, LinkEnd, Relation, System};
+
+impl System {
+    // Graphviz rendering of this system. Nested systems become clusters and
+    // link ends that can't be resolved point at red placeholder nodes.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph {\n    compound=true;\n    node [shape=box];\n");
+        let mut clusters = HashSet::new();
+        self.dot_frames(&mut out, 1, &mut clusters);
+        let mut unresolved = 0;
+        self.dot_links(&mut out, &clusters, &mut unresolved);
+        out.push_str("}\n");
+        return out;
+    }
+    fn dot_frames(&self, out: &mut String, depth: usize, clusters: &mut HashSet<FrameId>) {
+        let indent = "    ".repeat(depth);
+        for frame in self.frames.iter().flatten() {
+            let kind = frame.object().map_or("empty", |object| object.name());
+            let label = quote(&format!("{} ({})", frame.name, kind));
+            match frame.object().and_then(|object| object.system()) {
+                Some(system) => {
+                    clusters.insert(frame.id);
+                    let _ = writeln!(out, "{}subgraph cluster_{} {{", indent, node(frame.id));
+                    let _ = writeln!(out, "{}    label={};", indent, label);
+                    let _ = writeln!(
+                        out,
+                        "{}    {} [label={}, shape=folder];",
+                        indent,
+                        node(frame.id),
+                        label
+                    );
+                    system.dot_frames(out, depth + 1, clusters);
+                    let _ = writeln!(out, "{}}}", indent);
+                }
+                None => {
+                    let _ = writeln!(out, "{}{} [label={}];", indent, node(frame.id), label);
+                }
+            }
+        }
+    }
+    fn dot_links(&self, out: &mut String, clusters: &HashSet<FrameId>, unresolved: &mut usize) {
+        for link in self.links.iter() {
+            let a = self.dot_link_end(out, &link.a, unresolved);
+            let b = self.dot_link_end(out, &link.b, unresolved);
+            let mut attributes = vec![match link.relation {
+                Relation::Then => "style=solid",
+                Relation::Arg => "style=dashed, arrowhead=empty",
+                Relation::Catch => "style=dotted, color=orange",
+                Relation::After => "style=bold",
+            }
+            .to_string()];
+            if let Some(a) = a.0 {
+                if clusters.contains(&a) {
+                    attributes.push(format!("ltail=cluster_{}", node(a)));
+                }
+            }
+            if let Some(b) = b.0 {
+                if clusters.contains(&b) {
+                    attributes.push(format!("lhead=cluster_{}", node(b)));
+                }
+            }
+            if a.0.is_none() || b.0.is_none() {
+                attributes.push("color=red".to_string());
+            }
+            let _ = writeln!(out, "    {} -> {} [{}];", a.1, b.1, attributes.join(", "));
+        }
+        for frame in self.frames.iter().flatten() {
+            if let Some(system) = frame.object().and_then(|object| object.system()) {
+                system.dot_links(out, clusters, unresolved);
+            }
+        }
+    }
+    // Node the link end points at. Unresolved ends get a placeholder node
+    // labeled with the path they refer to.
+    fn dot_link_end(
+        &self,
+        out: &mut String,
+        link_end: &LinkEnd,
+        unresolved: &mut usize,
+    ) -> (Option<FrameId>, String) {
+        if let Some(frame) = link_end.resolve(self) {
+            if self.get(frame).is_some() {
+                return (Some(frame), node(frame));
+            }
+        }
+        let label = match *link_end {
+            LinkEnd::Frame(_) => "removed frame".to_string(),
+            LinkEnd::FrameElement(holder, ref path) => {
+                let holder = self.get(holder).map_or("?".to_string(), |holder| holder.name.clone());
+                format!("{}/{}", holder, path.join("/"))
+            }
+        };
+        *unresolved += 1;
+        let name = format!("unresolved{}", unresolved);
+        let _ = writeln!(
+            out,
+            "    {} [label={}, color=red, fontcolor=red, style=dashed];",
+            name,
+            quote(&label)
+        );
+        return (None, name);
+    }
+}
+
+fn node(frame: FrameId) -> String {
+    format!("frame{}", frame.generation)
+}
+
+fn quote(label: &str) -> String {
+    format!("\"{}\"", label.replace('\\', "\\\\").replace('"', "\\\""))
+}
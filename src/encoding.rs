@@ -0,0 +1,104 @@
+use std::error;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    UnsupportedVersion(u32),
+    UnexpectedEnd,
+    InvalidString,
+    InvalidTag(u8),
+    UnknownObject(String),
+    UnresolvedPath(Vec<String>),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            DecodeError::InvalidString => write!(f, "string is not valid UTF-8"),
+            DecodeError::InvalidTag(tag) => write!(f, "invalid tag {}", tag),
+            DecodeError::UnknownObject(ref name) => write!(f, "unknown object type {}", name),
+            DecodeError::UnresolvedPath(ref path) => {
+                write!(f, "element {} not found", path.join("/"))
+            }
+        }
+    }
+}
+
+impl error::Error for DecodeError {}
+
+pub fn write_u8(out: &mut Vec<u8>, value: u8) {
+    out.push(value);
+}
+
+pub fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+pub fn write_str(out: &mut Vec<u8>, string: &str) {
+    write_bytes(out, string.as_bytes());
+}
+
+pub fn write_names(out: &mut Vec<u8>, names: &[String]) {
+    write_u32(out, names.len() as u32);
+    for name in names {
+        write_str(out, name);
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes }
+    }
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        return Ok(head);
+    }
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        return Ok(self.take(1)?[0]);
+    }
+    pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        return Ok(u32::from_le_bytes(buf));
+    }
+    pub fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        return Ok(u64::from_le_bytes(buf));
+    }
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let len = self.read_u32()? as usize;
+        return Ok(self.take(len)?.to_vec());
+    }
+    pub fn read_string(&mut self) -> Result<String, DecodeError> {
+        String::from_utf8(self.read_bytes()?).map_err(|_| DecodeError::InvalidString)
+    }
+    pub fn read_names(&mut self) -> Result<Vec<String>, DecodeError> {
+        let mut names = Vec::new();
+        for _ in 0..self.read_u32()? {
+            names.push(self.read_string()?);
+        }
+        return Ok(names);
+    }
+}
@@ -0,0 +1,41 @@
+use std::error;
+use std::fmt;
+
+use crate::FrameId;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    // An update or finish arrived for a background task the loop doesn't know.
+    UnknownBackground(u64),
+    // A frame without an object was asked to run or update.
+    EmptyFrame(FrameId),
+    // The object of a frame asked to run can't run.
+    CannotRun(FrameId),
+    // A link or task refers to a frame that was removed.
+    DanglingFrame(FrameId),
+    // A link refers to an element that can't be found by name.
+    ElementNotFound(String),
+    // An object finished with `RunContext::fail` or `BackgroundTask::fail`.
+    Failed(String),
+    // Frame names can't be empty or contain '/'.
+    InvalidName(String),
+    // Another frame of the same system already has this name.
+    NameTaken(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownBackground(id) => write!(f, "unknown background task {}", id),
+            Error::EmptyFrame(frame) => write!(f, "frame {:?} has no object", frame),
+            Error::CannotRun(frame) => write!(f, "object of frame {:?} can't run", frame),
+            Error::DanglingFrame(frame) => write!(f, "frame {:?} no longer exists", frame),
+            Error::ElementNotFound(ref element) => write!(f, "element {} not found", element),
+            Error::Failed(ref message) => write!(f, "{}", message),
+            Error::InvalidName(ref name) => write!(f, "invalid frame name {:?}", name),
+            Error::NameTaken(ref name) => write!(f, "frame name {} is already taken", name),
+        }
+    }
+}
+
+impl error::Error for Error {}
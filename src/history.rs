@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use crate::{find_descendants, Frame, FrameId, Link, Object, System, TaskLoop};
+
+// Reversible edit of a `System`. Reverting an edit gives the edit that
+// reverts it back, so the same values serve `undo` and `redo`.
+pub enum Edit {
+    // A frame was added. Holds the frame while the addition is undone.
+    Frame(FrameId, Option<Box<Taken>>),
+    // A link was added. The flag tells whether it is currently in place.
+    Link(Link, bool),
+    // Links were removed, along with their positions. The flag tells whether
+    // they are currently out.
+    Unlink(Vec<(usize, Link)>, bool),
+    // Holds the object that the frame doesn't hold right now.
+    Adopt(FrameId, Option<Box<dyn Object>>),
+    Swap(FrameId, FrameId),
+    // Holds the name that the frame doesn't have right now.
+    Rename(FrameId, String),
+    // Edits of a transaction, in the order they were made.
+    Batch(Vec<Edit>),
+}
+
+// A frame taken out of the system holding it, along with the links that
+// reached it, so that it can be put back as it was.
+pub struct Taken {
+    // Path of the system that held the frame.
+    parent: Vec<FrameId>,
+    pub(crate) frame: Frame,
+    // Links reaching the frame or its elements, with the paths of the systems
+    // they were in.
+    links: Vec<(Vec<FrameId>, Link)>,
+    entry: bool,
+    output: bool,
+}
+
+impl System {
+    // Reverts the most recent edit made through `frame`, `frame_in`, `link`,
+    // `unlink`, `adopt`, `swap`, `rename` or `transaction`. Returns false if
+    // there is nothing to undo, or if the edit involves an object running on
+    // a worker. The edit can be undone once the object is back.
+    pub fn undo(&mut self, task_loop: &mut TaskLoop) -> bool {
+        let edit = match self.history.pop() {
+            Some(edit) => edit,
+            None => return false,
+        };
+        if !self.can_revert(&edit) {
+            self.history.push(edit);
+            return false;
+        }
+        let redo = self.revert(edit, task_loop);
+        self.undone.push(redo);
+        return true;
+    }
+    // Applies the most recently undone edit again. Returns false if there is
+    // nothing to redo, or if the edit involves an object running on a worker.
+    pub fn redo(&mut self, task_loop: &mut TaskLoop) -> bool {
+        let edit = match self.undone.pop() {
+            Some(edit) => edit,
+            None => return false,
+        };
+        if !self.can_revert(&edit) {
+            self.undone.push(edit);
+            return false;
+        }
+        let undo = self.revert(edit, task_loop);
+        self.history.push(undo);
+        return true;
+    }
+    // Also drops the objects kept for undoing adoptions and added frames.
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+        self.undone.clear();
+    }
+    // A new edit makes the undone ones impossible to redo.
+    pub(crate) fn record(&mut self, edit: Edit) {
+        self.history.push(edit);
+        self.undone.clear();
+    }
+    // Edits replacing or taking out objects away on workers would lose them.
+    fn can_revert(&self, edit: &Edit) -> bool {
+        match *edit {
+            Edit::Frame(id, None) | Edit::Adopt(id, _) => self.holds_object(id),
+            Edit::Swap(a, b) => self.holds_object(a) && self.holds_object(b),
+            Edit::Batch(ref edits) => edits.iter().all(|edit| self.can_revert(edit)),
+            _ => true,
+        }
+    }
+    pub(crate) fn revert(&mut self, edit: Edit, task_loop: &mut TaskLoop) -> Edit {
+        match edit {
+            Edit::Frame(id, None) => {
+                if !self.holds_object(id) {
+                    return Edit::Frame(id, None);
+                }
+                Edit::Frame(id, self.take_frame(id, task_loop).map(Box::new))
+            }
+            Edit::Frame(id, Some(taken)) => match self.restore_frame(taken) {
+                Ok(()) => Edit::Frame(id, None),
+                Err(taken) => Edit::Frame(id, Some(taken)),
+            },
+            Edit::Link(link, true) => {
+                if let Some(position) = self.links.iter().rposition(|other| *other == link) {
+                    self.links.remove(position);
+                }
+                Edit::Link(link, false)
+            }
+            Edit::Unlink(links, true) => {
+                for (position, link) in links.iter() {
+                    let position = (*position).min(self.links.len());
+                    self.links.insert(position, link.clone());
+                }
+                Edit::Unlink(links, false)
+            }
+            Edit::Unlink(links, false) => {
+                for (position, link) in links.iter().rev() {
+                    let position = match self.links.get(*position) {
+                        Some(other) if other == link => Some(*position),
+                        _ => self.links.iter().rposition(|other| other == link),
+                    };
+                    if let Some(position) = position {
+                        self.links.remove(position);
+                    }
+                }
+                Edit::Unlink(links, true)
+            }
+            Edit::Link(link, false) => {
+                self.links.push(link.clone());
+                Edit::Link(link, true)
+            }
+            Edit::Adopt(frame, object) => {
+                if !self.holds_object(frame) {
+                    return Edit::Adopt(frame, object);
+                }
+                let replaced = self.get_mut(frame).unwrap().adopt(object);
+                Edit::Adopt(frame, replaced)
+            }
+            Edit::Swap(a, b) => {
+                if self.holds_object(a) && self.holds_object(b) {
+                    self.swap_objects(a, b, task_loop);
+                }
+                Edit::Swap(a, b)
+            }
+            Edit::Rename(frame, name) => {
+                if self.get(frame).is_none() {
+                    return Edit::Rename(frame, name);
+                }
+                let old = self.rename_frame(frame, &name);
+                Edit::Rename(frame, old)
+            }
+            Edit::Batch(edits) => {
+                let mut reverted: Vec<Edit> =
+                    edits.into_iter().rev().map(|edit| self.revert(edit, task_loop)).collect();
+                reverted.reverse();
+                Edit::Batch(reverted)
+            }
+        }
+    }
+    // Takes `frame` out of the system holding it, dropping the links of every
+    // enclosing system that reach it or its elements.
+    pub(crate) fn take_frame(&mut self, frame: FrameId, task_loop: &mut TaskLoop) -> Option<Taken> {
+        let path = self.path(frame)?;
+        let mut removed = HashMap::new();
+        if let Some(object) = self.get(frame).and_then(|frame| frame.object()) {
+            find_descendants(object, &[], &mut removed);
+        }
+        removed.insert(frame, Vec::new());
+        let mut links = Vec::new();
+        for depth in 0..path.len() {
+            if let Some(system) = self.subsystem_mut(&path[..depth]) {
+                let (dropped, kept) = system.links.drain(..).partition(|link: &Link| {
+                    removed.contains_key(&link.a.frame()) || removed.contains_key(&link.b.frame())
+                });
+                system.links = kept;
+                links.extend(dropped.into_iter().map(|link| (path[..depth].to_vec(), link)));
+            }
+        }
+        for id in removed.keys() {
+            task_loop.cancel(self, *id);
+            task_loop.unschedule(*id);
+        }
+        let parent = path[..path.len() - 1].to_vec();
+        let system = self.subsystem_mut(&parent)?;
+        let entry = system.entries.contains(&frame);
+        let output = system.outputs.contains(&frame);
+        system.entries.retain(|&entry| entry != frame);
+        system.outputs.retain(|&output| output != frame);
+        let taken = system.frames[frame.index].take()?;
+        system.free.push(frame.index);
+        return Some(Taken {
+            parent,
+            frame: taken,
+            links,
+            entry,
+            output,
+        });
+    }
+    // Puts back a frame taken by `take_frame`. Gives it back if the system
+    // that held it is gone or its slot was reused.
+    fn restore_frame(&mut self, taken: Box<Taken>) -> Result<(), Box<Taken>> {
+        let id = taken.frame.id;
+        let system = match self.subsystem_mut(&taken.parent) {
+            Some(system) if system.frames[id.index].is_none() => system,
+            _ => return Err(taken),
+        };
+        system.free.retain(|&index| index != id.index);
+        let Taken {
+            frame,
+            links,
+            entry,
+            output,
+            ..
+        } = *taken;
+        system.frames[id.index] = Some(frame);
+        if entry {
+            system.entries.push(id);
+        }
+        if output {
+            system.outputs.push(id);
+        }
+        for (path, link) in links {
+            if let Some(system) = self.subsystem_mut(&path) {
+                system.links.push(link);
+            }
+        }
+        return Ok(());
+    }
+}
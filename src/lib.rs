@@ -1,29 +1,116 @@
+#![allow(clippy::needless_return)]
+
 use std::collections::VecDeque;
-use std::slice;
-use std::ptr;
 use std::mem;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::any::Any;
+use std::convert::Infallible;
+use std::cell::RefCell;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::panic;
+use std::pin::Pin;
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc::{self, Sender, Receiver};
-
-pub trait Object {
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+mod clock;
+mod dot;
+mod encoding;
+mod error;
+mod history;
+mod port;
+mod registry;
+mod snapshot;
+mod template;
+mod trace;
+mod transaction;
+mod validate;
+mod workers;
+
+use encoding::Reader;
+use history::Edit;
+use workers::{Job, WorkerPool};
+pub use clock::{Clock, MockClock, SystemClock};
+pub use encoding::DecodeError;
+pub use error::Error;
+pub use port::Port;
+pub use registry::{ObjectRegistry, UnknownType};
+pub use snapshot::Snapshot;
+pub use trace::{TraceEvent, TraceRecorder, Tracer};
+pub use transaction::Transaction;
+pub use validate::Diagnostic;
+
+// Version of the binary format produced by `System::serialize`.
+const FORMAT_VERSION: u32 = 1;
+
+// Objects are `Send` so that a `TaskLoop` can run them on worker threads.
+pub trait Object: Send {
     fn name(&self) -> &'static str;
+    // Frames holding objects that can't run fail with `Error::CannotRun`
+    // instead of running.
     fn can_run(&self) -> bool {
         false
     }
     fn run(&mut self, _: RunContext) {
         unimplemented!()
     }
+    // Async variant of `run`. If it returns a future, `run` isn't called and
+    // the frame finishes once the future resolves, failing on `Err`. The
+    // future is polled by the `TaskLoop` on its own thread.
+    fn run_async(&mut self, _: &RunContext) -> Option<RunFuture> {
+        None
+    }
+    // Called instead of `run` for frames that were running when the
+    // `Snapshot` they were restored from was taken, so that objects can pick
+    // up their background work where it was. Objects using `run_async` are
+    // simply run again.
+    fn restart(&mut self, ctx: RunContext) {
+        self.run(ctx)
+    }
     fn update(&mut self, _: Update) {
         unimplemented!()
     }
-    fn concrete(&mut self) -> ConcreteObject;
-    fn deserialize(&mut self, Vec<u8>) {}
+    // Called when the loop cancels the background work of this object.
+    // Updates sent afterwards are dropped.
+    fn cancel(&mut self) {}
+    fn concrete(&mut self) -> ConcreteObject<'_>;
+    // Shared counterpart of `concrete` for objects that are systems.
+    fn system(&self) -> Option<&System> {
+        None
+    }
+    fn deserialize(&mut self, _: Vec<u8>) {}
     fn serialize(&self) -> Vec<u8> {
         Vec::new()
     }
-    fn elements(&self) -> &[*mut Frame] {
-        unsafe { slice::from_raw_parts(ptr::null(), 0) }
+    // Copy of this object for `System::instantiate`. Objects without one are
+    // copied by passing their serialized state to a new object.
+    fn clone_object(&self) -> Option<Box<dyn Object>> {
+        None
+    }
+    fn elements(&self) -> Vec<&Frame> {
+        Vec::new()
+    }
+    // Value handed over to the targets of `Relation::Arg` links.
+    fn output(&self) -> Option<Value> {
+        None
+    }
+}
+
+// Extension of `Object` for objects whose background work sends updates of a
+// single type. Their `run` starts it with `RunContext::background_for(self)`,
+// which only takes updates of that type, and their `Object::update` forwards
+// to `dispatch`.
+pub trait Updatable: Object {
+    type Update: Send + 'static;
+    fn receive(&mut self, update: Self::Update);
+    fn dispatch(&mut self, update: Update) {
+        match update.downcast::<Self::Update>() {
+            Ok(update) => self.receive(*update),
+            Err(_) => panic!("{} received an update of another type", self.name()),
+        }
     }
 }
 
@@ -31,49 +118,102 @@ pub trait FrameData {
     fn new() -> Self;
 }
 
+// Handle to a frame owned by some `System`. Generations are unique across
+// all systems, so a handle never resolves to a frame other than its own.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FrameId {
+    index: usize,
+    generation: u64,
+}
+
+static GENERATION: AtomicU64 = AtomicU64::new(1);
+
 pub struct System {
-    frame: Option<*mut Frame>,
-    frames: Vec<*mut Frame>,
+    frames: Vec<Option<Frame>>,
+    free: Vec<usize>,
     links: Vec<Link>,
+    error_handler: Option<FrameId>,
+    // Frames scheduled when a frame holding this system is scheduled.
+    entries: Vec<FrameId>,
+    // Frames that finish a frame holding this system when they finish.
+    outputs: Vec<FrameId>,
+    // Paths to frames of subsystems found by earlier lookups. Subsystems can
+    // be edited on their own, so paths are checked before being used.
+    paths: RefCell<HashMap<FrameId, Vec<FrameId>>>,
+    // Edits that `undo` reverts, most recent last.
+    history: Vec<Edit>,
+    // Edits that `redo` applies again, most recently undone last.
+    undone: Vec<Edit>,
 }
 
 pub struct Frame {
-    parent: *mut Object,
+    id: FrameId,
     name: String,
-    object: Option<*mut Object>,
+    object: Option<Box<dyn Object>>,
     scheduled: bool,
     running: bool,
+    // The object is running on a worker, leaving the frame without it.
+    away: bool,
+    // Errors waiting to be handed over to this frame when it runs.
+    errors: Vec<Error>,
+    // `After` predecessors that finished since this frame last ran.
+    arrived: HashSet<FrameId>,
 }
 
-pub enum ConcreteObject {
-    Other(*mut Object),
-    System(*mut System),
+pub enum ConcreteObject<'a> {
+    Other(&'a mut dyn Object),
+    System(&'a mut System),
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 struct Link {
     relation: Relation,
     a: LinkEnd,
     b: LinkEnd,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Relation {
     Then,
     Arg,
+    // Followed instead of `Then` when the object fails.
+    Catch,
+    // Like `Then`, but the target waits until all of its `After`
+    // predecessors have finished.
+    After,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 enum LinkEnd {
-    Frame(*mut Frame),
-    FrameElement(*mut Frame, String),
+    Frame(FrameId),
+    // Path of names leading to a frame inside the object held by a frame.
+    FrameElement(FrameId, Vec<String>),
 }
 
-type Update = Box<Any + Send>;
+pub type Update = Box<dyn Any + Send>;
+
+pub type Value = Arc<dyn Any + Send + Sync>;
+
+// Future returned by `Object::run_async`. It can't borrow its object, so
+// results go back through `Updater::send_update`.
+pub type RunFuture = Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>;
 
 enum TaskEvent {
     Update(Update),
-    Drop,
+    Drop(Option<Error>),
+    // The future of a background task can make progress.
+    Wake,
+    // A worker finished running the object of a frame.
+    Ran(Box<dyn Object>, Outcome),
+}
+
+// How a call to `Object::run` ended.
+enum Outcome {
+    Finished(Option<Error>),
+    Background,
+    // Also counts the `Updater`s handed out during the run.
+    Async(RunFuture, Weak<()>),
+    Panicked(Box<dyn Any + Send>),
 }
 
 pub struct TaskLoop {
@@ -82,50 +222,155 @@ pub struct TaskLoop {
     tx: Sender<(u64, TaskEvent)>,
     rx: Receiver<(u64, TaskEvent)>,
     tasks: VecDeque<Task>,
+    workers: Option<WorkerPool>,
+    // Tasks whose objects are away on worker threads.
+    in_flight: HashMap<u64, Task>,
+    // Background events that arrived before their worker returned.
+    deferred: Vec<(u64, TaskEvent)>,
+    // Futures of background tasks started with `Object::run_async`, along
+    // with the `Updater`s handed out for them.
+    futures: HashMap<u64, (RunFuture, Weak<()>)>,
+    // Cancelled background tasks, whose late events are ignored until their
+    // `BackgroundTask` is dropped.
+    cancelled: HashSet<u64>,
+    // Dropped futures whose `Updater`s are still around. Their updates are
+    // ignored.
+    retired: HashMap<u64, Weak<()>>,
+    clock: Box<dyn Clock>,
+    // Keyed by deadline and `TimerId` so that timers due at the same time
+    // fire in the order they were set.
+    timers: BTreeMap<(Duration, u64), Timer>,
+    tracer: Option<Box<dyn Tracer>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TimerId(u64);
+
+struct Timer {
+    frame: FrameId,
+    period: Option<Duration>,
+}
+
+pub struct BackgroundTask<U> {
+    id: u64,
+    tx: Sender<(u64, TaskEvent)>,
+    error: Option<Error>,
+    token: CancelToken,
+    update: PhantomData<fn(U)>,
+}
+
+// Set once the loop cancels a run. Background work should check it and
+// stop early.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+pub struct Updater<U> {
+    id: u64,
+    tx: Sender<(u64, TaskEvent)>,
+    alive: Arc<()>,
+    update: PhantomData<fn(U)>,
 }
 
-pub struct BackgroundTask {
+// Wakes a future by asking its loop to poll it again.
+struct LoopWaker {
     id: u64,
     tx: Sender<(u64, TaskEvent)>,
 }
 
+#[derive(Clone)]
 struct Task {
-    frame: *mut Frame,
+    frame: FrameId,
+    token: CancelToken,
+    // Continues a run interrupted by a `Snapshot`.
+    restart: bool,
 }
 
 pub struct RunContext<'a> {
-    task: Option<Task>,
-    task_loop: &'a mut TaskLoop,
+    id: u64,
+    tx: Sender<(u64, TaskEvent)>,
+    outcome: &'a mut Option<Outcome>,
+    args: HashMap<String, Value>,
+    errors: Vec<Error>,
+    token: CancelToken,
+    restart: bool,
+    // Shared with the `Updater`s of the run.
+    updaters: Arc<()>,
 }
 
 impl LinkEnd {
-    fn frame(&self) -> *mut Frame {
-        match self {
-            &LinkEnd::Frame(frame) => frame,
-            &LinkEnd::FrameElement(frame, _) => frame,
+    fn frame(&self) -> FrameId {
+        match *self {
+            LinkEnd::Frame(frame) => frame,
+            LinkEnd::FrameElement(frame, _) => frame,
+        }
+    }
+    fn resolve(&self, system: &System) -> Option<FrameId> {
+        match *self {
+            LinkEnd::Frame(frame) => Some(frame),
+            LinkEnd::FrameElement(frame, ref element) => {
+                system.get(frame).and_then(|frame| frame.resolve(element))
+            }
+        }
+    }
+    fn element(&self) -> &[String] {
+        match *self {
+            LinkEnd::Frame(_) => &[],
+            LinkEnd::FrameElement(_, ref element) => element,
         }
     }
+    // Whether the end leads to a frame that exists.
+    fn resolves(&self, system: &System) -> bool {
+        self.resolve(system).is_some_and(|frame| system.get(frame).is_some())
+    }
 }
 
-fn find_element(object: *const Object, name: &String) -> Option<*mut Frame> {
-    let mut deq: VecDeque<*mut Frame> = VecDeque::new();
-    unsafe {
-        deq.extend((*object).elements());
-        while let Some(frame) = deq.pop_front() {
-            let frame_name: &String = &(*frame).name;
-            if frame_name == name {
-                return Some(frame);
-            }
-            if let Some(object) = (*frame).object {
-                deq.extend((*object).elements());
+// Whether a frame inside `object` has its object on a worker.
+fn away_inside(object: &dyn Object) -> bool {
+    object.elements().into_iter().any(|frame| frame.away || frame.object().is_some_and(away_inside))
+}
+
+// Frames leading from `object` down to `id`, including `id` itself.
+fn find_path(object: &dyn Object, id: FrameId) -> Option<Vec<FrameId>> {
+    for frame in object.elements() {
+        if frame.id == id {
+            return Some(vec![id]);
+        }
+        if let Some(ref object) = frame.object {
+            if let Some(mut path) = find_path(&**object, id) {
+                path.insert(0, frame.id);
+                return Some(path);
             }
         }
     }
     return None;
 }
 
+fn resolve_element_path(object: &dyn Object, path: &[String]) -> Option<FrameId> {
+    let (first, rest) = path.split_first()?;
+    let frame = object.elements().into_iter().find(|frame| &frame.name == first)?;
+    if rest.is_empty() {
+        return Some(frame.id);
+    }
+    return resolve_element_path(frame.object()?, rest);
+}
+
+// Paths of all frames inside `object`, relative to it.
+fn find_descendants(object: &dyn Object, prefix: &[String], paths: &mut HashMap<FrameId, Vec<String>>) {
+    for frame in object.elements() {
+        let mut path = prefix.to_vec();
+        path.push(frame.name.clone());
+        if let Some(ref object) = frame.object {
+            find_descendants(&**object, &path, paths);
+        }
+        paths.insert(frame.id, path);
+    }
+}
+
 impl TaskLoop {
     pub fn new() -> TaskLoop {
+        TaskLoop::with_clock(Box::new(SystemClock::new()))
+    }
+    pub fn with_clock(clock: Box<dyn Clock>) -> TaskLoop {
         let (tx, rx) = mpsc::channel();
         TaskLoop {
             counter: 0,
@@ -133,262 +378,905 @@ impl TaskLoop {
             tx,
             rx,
             tasks: VecDeque::new(),
+            workers: None,
+            in_flight: HashMap::new(),
+            deferred: Vec::new(),
+            futures: HashMap::new(),
+            cancelled: HashSet::new(),
+            retired: HashMap::new(),
+            clock,
+            timers: BTreeMap::new(),
+            tracer: None,
         }
     }
-    fn background(&mut self, task: Task) -> BackgroundTask {
-        self.counter += 1;
-        self.background.insert(self.counter, task);
-        return BackgroundTask {
-            id: self.counter,
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) {
+        self.tracer = tracer;
+    }
+    fn trace(&mut self, event: TraceEvent) {
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.trace(self.clock.now(), &event);
+        }
+    }
+    // Runs objects on `count` worker threads, or on the thread calling
+    // `run_one` if `count` is zero. Frames still run one at a time and `Then`
+    // successors start only after their predecessor finished. The graph
+    // should only be edited while no worker is busy.
+    pub fn set_workers(&mut self, count: usize) {
+        self.workers = if count == 0 {
+            None
+        } else {
+            Some(WorkerPool::new(count))
+        };
+    }
+    fn can_dispatch(&self) -> bool {
+        match self.workers {
+            Some(ref workers) => self.in_flight.len() < workers.size(),
+            None => true,
+        }
+    }
+    // Takes the first task whose frame isn't already running.
+    fn next_task(&mut self, system: &System) -> Option<Task> {
+        let position = self.tasks.iter().position(|task| {
+            !system.get(task.frame).is_some_and(|frame| frame.running)
+        })?;
+        return self.tasks.remove(position);
+    }
+    fn complete(
+        &mut self,
+        system: &mut System,
+        id: u64,
+        task: Task,
+        outcome: Outcome,
+    ) -> Result<(), Error> {
+        let cancelled = task.token.is_cancelled();
+        match outcome {
+            Outcome::Finished(_) if cancelled => {
+                if let Some(frame) = system.get_mut(task.frame) {
+                    frame.running = false;
+                }
+                self.trace(TraceEvent::Cancel { frame: task.frame, task: id });
+                return Ok(());
+            }
+            Outcome::Finished(error) => return task.finish(system, self, id, error),
+            Outcome::Background => {}
+            Outcome::Async(future, updaters) => {
+                self.futures.insert(id, (future, updaters));
+            }
+            Outcome::Panicked(payload) => panic::resume_unwind(payload),
+        }
+        self.trace(TraceEvent::BackgroundStart { frame: task.frame, task: id });
+        self.background.insert(id, task);
+        if cancelled {
+            self.cancel_task(system, id);
+        }
+        let (replay, deferred) = mem::take(&mut self.deferred)
+            .into_iter()
+            .partition::<Vec<_>, _>(|&(event_id, _)| event_id == id);
+        self.deferred = deferred;
+        let mut result = Ok(());
+        for event in replay {
+            result = result.and(self.handle_event(system, event));
+        }
+        self.poll(id);
+        return result;
+    }
+    // Once the future resolves, its task finishes through the channel, so
+    // that updates sent before that are handled first.
+    fn poll(&mut self, id: u64) {
+        let future = match self.futures.get_mut(&id) {
+            Some((future, _)) => future,
+            None => return,
+        };
+        let waker = Waker::from(Arc::new(LoopWaker {
+            id,
             tx: self.tx.clone(),
+        }));
+        if let Poll::Ready(result) = future.as_mut().poll(&mut Context::from_waker(&waker)) {
+            self.drop_future(id);
+            self.tx.send((id, TaskEvent::Drop(result.err()))).unwrap();
+        }
+    }
+    // Keeps the id of a dropped future for as long as any of its `Updater`s
+    // is left, and forgets the ids whose `Updater`s are all gone.
+    fn drop_future(&mut self, id: u64) -> bool {
+        let updaters = match self.futures.remove(&id) {
+            Some((_, updaters)) => updaters,
+            None => return false,
+        };
+        self.retired.retain(|_, updaters| updaters.strong_count() > 0);
+        if updaters.strong_count() > 0 {
+            self.retired.insert(id, updaters);
+        }
+        return true;
+    }
+    // Cancels the runs of `frame` in progress. Their background work sees its
+    // `CancelToken` set, futures are dropped, and the frame finishes without
+    // following any links. Returns false if the frame wasn't running.
+    pub fn cancel(&mut self, system: &mut System, frame: FrameId) -> bool {
+        let ids: Vec<u64> = self
+            .background
+            .iter()
+            .chain(self.in_flight.iter())
+            .filter(|(_, task)| task.frame == frame)
+            .map(|(&id, _)| id)
+            .collect();
+        for &id in ids.iter() {
+            self.cancel_task(system, id);
+        }
+        return !ids.is_empty();
+    }
+    // Tasks away on a worker are only marked and get cancelled once their
+    // object is back.
+    fn cancel_task(&mut self, system: &mut System, id: u64) {
+        if let Some(task) = self.in_flight.get(&id) {
+            task.token.cancel();
+            return;
+        }
+        let task = match self.background.remove(&id) {
+            Some(task) => task,
+            None => return,
         };
+        if !self.drop_future(id) {
+            self.cancelled.insert(id);
+        }
+        self.trace(TraceEvent::Cancel { frame: task.frame, task: id });
+        if let Some(frame) = system.get_mut(task.frame) {
+            frame.running = false;
+            if let Some(ref mut object) = frame.object {
+                object.cancel();
+            }
+        }
+        task.token.cancel();
     }
     fn post(&mut self, task: Task) {
         self.tasks.push_back(task);
     }
-    pub fn run_iterations(&mut self, n: u32) {
-        for _ in 0..n {
-            self.run_one();
-        }
+    // Drops any pending runs of `frame`, including its timers.
+    pub fn unschedule(&mut self, frame: FrameId) {
+        self.tasks.retain(|task| task.frame != frame);
+        self.timers.retain(|_, timer| timer.frame != frame);
     }
-    pub fn run_until_done(&mut self) {
-        while self.run_one() {}
+    fn add_timer(&mut self, deadline: Duration, frame: FrameId, period: Option<Duration>) -> TimerId {
+        self.counter += 1;
+        self.timers.insert((deadline, self.counter), Timer { frame, period });
+        return TimerId(self.counter);
+    }
+    // Schedules `frame` once the clock reaches `deadline`.
+    pub fn schedule_at(&mut self, frame: FrameId, deadline: Duration) -> TimerId {
+        self.add_timer(deadline, frame, None)
+    }
+    pub fn schedule_after(&mut self, frame: FrameId, delay: Duration) -> TimerId {
+        let deadline = self.now() + delay;
+        self.add_timer(deadline, frame, None)
+    }
+    // Schedules `frame` every `period`, starting one period from now. The
+    // loop never runs out of work until the timer is cancelled. Panics if
+    // `period` is zero.
+    pub fn schedule_every(&mut self, frame: FrameId, period: Duration) -> TimerId {
+        assert!(!period.is_zero(), "timer period must not be zero");
+        let deadline = self.now() + period;
+        self.add_timer(deadline, frame, Some(period))
+    }
+    // Returns false if the timer already fired or was cancelled.
+    pub fn cancel_timer(&mut self, timer: TimerId) -> bool {
+        let count = self.timers.len();
+        self.timers.retain(|&(_, id), _| id != timer.0);
+        return self.timers.len() != count;
+    }
+    fn next_deadline(&self) -> Option<Duration> {
+        self.timers.keys().next().map(|&(deadline, _)| deadline)
+    }
+    // Periodic timers fire at most once per call, even if their next
+    // deadline already passed, so that short periods can't stall the loop.
+    fn fire_timers(&mut self, system: &mut System) -> Result<(), Error> {
+        let now = self.now();
+        let mut result = Ok(());
+        let mut rearmed = Vec::new();
+        while let Some(&(deadline, id)) = self.timers.keys().next() {
+            if deadline > now {
+                break;
+            }
+            let timer = self.timers.remove(&(deadline, id)).unwrap();
+            result = result.and(system.schedule(timer.frame, self));
+            if let Some(period) = timer.period {
+                rearmed.push(((deadline + period, id), timer));
+            }
+        }
+        self.timers.extend(rearmed);
+        return result;
     }
-    pub fn run_one(&mut self) -> bool {
-        match self.rx.try_recv() {
-            Ok((id, TaskEvent::Update(update))) => {
-                self.background.get_mut(&id).unwrap().update(update);
-                true
+    pub fn run_iterations(&mut self, system: &mut System, n: u32) -> Result<(), Error> {
+        for _ in 0..n {
+            self.run_one(system)?;
+        }
+        return Ok(());
+    }
+    pub fn run_until_done(&mut self, system: &mut System) -> Result<(), Error> {
+        while self.run_one(system)? {}
+        return Ok(());
+    }
+    // Errors that can't be handed over to an error handler are returned. The
+    // loop stays usable afterwards.
+    pub fn run_one(&mut self, system: &mut System) -> Result<bool, Error> {
+        if let Ok(event) = self.rx.try_recv() {
+            self.handle_event(system, event)?;
+            return Ok(true);
+        }
+        self.fire_timers(system)?;
+        if self.can_dispatch() {
+            if let Some(task) = self.next_task(system) {
+                task.run(system, self)?;
+                return Ok(true);
             }
-            Ok((id, TaskEvent::Drop)) => {
-                let task = self.background.remove(&id).unwrap();
-                task.finish(self);
-                true
+        }
+        let waiting = !self.background.is_empty() || !self.in_flight.is_empty();
+        match self.next_deadline() {
+            Some(deadline) => {
+                let wait = deadline.saturating_sub(self.now());
+                if waiting {
+                    if let Ok(event) = self.rx.recv_timeout(self.clock.blocking(wait)) {
+                        self.handle_event(system, event)?;
+                        return Ok(true);
+                    }
+                }
+                self.clock.sleep(deadline.saturating_sub(self.now()));
+                Ok(true)
+            }
+            None if !waiting => Ok(false),
+            None => {
+                let event = self.rx.recv().expect("TaskLoop holds a sender");
+                self.handle_event(system, event)?;
+                Ok(true)
             }
-            _ => {
-                match self.tasks.pop_front() {
+        }
+    }
+    fn handle_event(
+        &mut self,
+        system: &mut System,
+        (id, event): (u64, TaskEvent),
+    ) -> Result<(), Error> {
+        match event {
+            TaskEvent::Update(update) => {
+                match self.background.get(&id).cloned() {
                     Some(task) => {
-                        task.run(self);
-                        true
-                    }
-                    None => {
-                        if self.background.is_empty() {
-                            false
-                        } else {
-                            match self.rx.recv() {
-                                Ok((id, TaskEvent::Update(update))) => {
-                                    self.background.get_mut(&id).unwrap().update(update);
-                                    true
-                                }
-                                Ok((id, TaskEvent::Drop)) => {
-                                    let task = self.background.remove(&id).unwrap();
-                                    task.finish(self);
-                                    true
-                                }
-                                _ => panic!(),
-                            }
-                        }
+                        self.trace(TraceEvent::Update { frame: task.frame, task: id });
+                        task.update(system, self, update)
                     }
+                    None if self.cancelled.contains(&id) || self.retired.contains_key(&id) => Ok(()),
+                    None => self.defer(id, TaskEvent::Update(update)),
+                }
+            }
+            TaskEvent::Drop(error) => {
+                match self.background.remove(&id) {
+                    Some(task) => task.finish(system, self, id, error),
+                    None if self.cancelled.remove(&id) => Ok(()),
+                    None => self.defer(id, TaskEvent::Drop(error)),
+                }
+            }
+            // Wakes of futures that already resolved are ignored.
+            TaskEvent::Wake => {
+                self.poll(id);
+                Ok(())
+            }
+            TaskEvent::Ran(object, outcome) => {
+                let task = match self.in_flight.remove(&id) {
+                    Some(task) => task,
+                    None => return Err(Error::UnknownBackground(id)),
+                };
+                // If the frame was removed meanwhile, the object goes with it.
+                // Frames whose object is away can't adopt another one.
+                if let Some(frame) = system.get_mut(task.frame) {
+                    frame.away = false;
+                    frame.object = Some(object);
                 }
+                self.complete(system, id, task, outcome)
             }
         }
     }
+    fn defer(&mut self, id: u64, event: TaskEvent) -> Result<(), Error> {
+        if self.in_flight.contains_key(&id) {
+            self.deferred.push((id, event));
+            return Ok(());
+        }
+        return Err(Error::UnknownBackground(id));
+    }
+}
+
+impl Default for TaskLoop {
+    fn default() -> TaskLoop {
+        TaskLoop::new()
+    }
+}
+
+impl<U: Send + 'static> BackgroundTask<U> {
+    pub fn send_update(&mut self, update: U) {
+        self.tx.send((self.id, TaskEvent::Update(Box::new(update)))).unwrap();
+    }
+    // Finishes the frame with an error, following its `Relation::Catch` links.
+    pub fn fail(mut self, error: Error) {
+        self.error = Some(error);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+    pub fn token(&self) -> CancelToken {
+        self.token.clone()
+    }
 }
 
-impl BackgroundTask {
-    pub fn send_update(&mut self, update: Update) {
-        self.tx.send((self.id, TaskEvent::Update(update))).unwrap();
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+    fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
     }
 }
 
-impl Drop for BackgroundTask {
+impl<U> Drop for BackgroundTask<U> {
     fn drop(&mut self) {
-        self.tx.send((self.id, TaskEvent::Drop)).unwrap();
+        self.tx.send((self.id, TaskEvent::Drop(self.error.take()))).unwrap();
+    }
+}
+
+impl<U: Send + 'static> Updater<U> {
+    pub fn send_update(&self, update: U) {
+        self.tx.send((self.id, TaskEvent::Update(Box::new(update)))).unwrap();
+    }
+}
+
+impl<U> Clone for Updater<U> {
+    fn clone(&self) -> Updater<U> {
+        Updater {
+            id: self.id,
+            tx: self.tx.clone(),
+            alive: self.alive.clone(),
+            update: PhantomData,
+        }
+    }
+}
+
+impl Wake for LoopWaker {
+    fn wake(self: Arc<Self>) {
+        // The loop may be gone already.
+        let _ = self.tx.send((self.id, TaskEvent::Wake));
+    }
+}
+
+// Calls `Object::run_async`, falling back to `Object::run` or
+// `Object::restart`.
+fn run_object(object: &mut dyn Object, ctx: RunContext) {
+    match object.run_async(&ctx) {
+        Some(future) => {
+            *ctx.outcome = Some(Outcome::Async(future, Arc::downgrade(&ctx.updaters)));
+        }
+        None if ctx.restart => object.restart(ctx),
+        None => object.run(ctx),
     }
 }
 
 impl<'a> RunContext<'a> {
-    pub fn background(mut self) -> BackgroundTask {
-        return self.task_loop.background(self.task.take().unwrap());
+    // For background work that sends no updates.
+    pub fn background(self) -> BackgroundTask<Infallible> {
+        self.start_background()
+    }
+    /// Handle sending updates of the type `object` receives. Other types are
+    /// rejected when compiling:
+    ///
+    /// ```compile_fail
+    /// use system::{ConcreteObject, Object, RunContext, Updatable, Update};
+    ///
+    /// struct Counter;
+    ///
+    /// impl Object for Counter {
+    ///     fn name(&self) -> &'static str {
+    ///         "Counter"
+    ///     }
+    ///     fn concrete(&mut self) -> ConcreteObject<'_> {
+    ///         ConcreteObject::Other(self)
+    ///     }
+    ///     fn run(&mut self, ctx: RunContext) {
+    ///         ctx.background_for(self).send_update(1u32);
+    ///     }
+    ///     fn update(&mut self, update: Update) {
+    ///         self.dispatch(update);
+    ///     }
+    /// }
+    ///
+    /// impl Updatable for Counter {
+    ///     type Update = String;
+    ///     fn receive(&mut self, _: String) {}
+    /// }
+    /// ```
+    pub fn background_for<T: Updatable>(self, _: &T) -> BackgroundTask<T::Update> {
+        self.start_background()
+    }
+    fn start_background<U: Send + 'static>(self) -> BackgroundTask<U> {
+        *self.outcome = Some(Outcome::Background);
+        return BackgroundTask {
+            id: self.id,
+            tx: self.tx.clone(),
+            error: None,
+            token: self.token.clone(),
+            update: PhantomData,
+        };
+    }
+    // Finishes the frame with an error, following its `Relation::Catch` links.
+    // Without any, the error goes to the error handler.
+    pub fn fail(self, error: Error) {
+        *self.outcome = Some(Outcome::Finished(Some(error)));
+    }
+    // Output of the frame linked with `Relation::Arg`, keyed by its name.
+    pub fn arg(&self, name: &str) -> Option<&Value> {
+        self.args.get(name)
+    }
+    pub fn args(&self) -> &HashMap<String, Value> {
+        &self.args
+    }
+    // Errors reported to this frame as an error handler since its last run.
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+    // Lets the future returned by `Object::run_async` hand the token to
+    // helper threads. The future itself is dropped when cancelled.
+    pub fn token(&self) -> CancelToken {
+        self.token.clone()
+    }
+    // Lets the future returned by `Object::run_async` send updates of the
+    // type `object` receives. Updates sent after the future resolved or was
+    // cancelled are ignored.
+    pub fn updater_for<T: Updatable>(&self, _: &T) -> Updater<T::Update> {
+        Updater {
+            id: self.id,
+            tx: self.tx.clone(),
+            alive: self.updaters.clone(),
+            update: PhantomData,
+        }
     }
 }
 
 impl<'a> Drop for RunContext<'a> {
     fn drop(&mut self) {
-        if let Some(task) = self.task.take() {
-            task.finish(self.task_loop);
+        if self.outcome.is_none() {
+            *self.outcome = Some(Outcome::Finished(None));
         }
     }
 }
 
 impl Task {
     // Executed by TaskLoop
-    fn run(self, task_loop: &mut TaskLoop) {
-        unsafe {
-            (*self.frame).scheduled = false;
-            (*self.frame).running = true;
-            match (*self.frame).object {
-                Some(object) => {
-                    (*object).run(RunContext {
-                        task: Some(self),
-                        task_loop: task_loop,
-                    });
-                }
-                None => unimplemented!(),
+    fn run(self, system: &mut System, task_loop: &mut TaskLoop) -> Result<(), Error> {
+        let args = system.collect_args(self.frame);
+        let frame = match system.get_mut(self.frame) {
+            Some(frame) => frame,
+            None => return Err(Error::DanglingFrame(self.frame)),
+        };
+        // A restarted run doesn't consume a pending schedule of its frame.
+        if !self.restart {
+            frame.scheduled = false;
+        }
+        match frame.object {
+            None => return system.report(self.frame, Error::EmptyFrame(self.frame), task_loop),
+            Some(ref object) if !object.can_run() => {
+                return system.report(self.frame, Error::CannotRun(self.frame), task_loop);
             }
+            _ => {}
+        }
+        frame.running = true;
+        let errors = mem::take(&mut frame.errors);
+        task_loop.counter += 1;
+        let id = task_loop.counter;
+        let tx = task_loop.tx.clone();
+        task_loop.trace(TraceEvent::RunStart { frame: self.frame, task: id });
+        if let Some(ref workers) = task_loop.workers {
+            frame.away = true;
+            workers.run(Job {
+                id,
+                object: frame.object.take().unwrap(),
+                args,
+                errors,
+                tx,
+                token: self.token.clone(),
+                restart: self.restart,
+            });
+            task_loop.in_flight.insert(id, self);
+            return Ok(());
+        }
+        let mut outcome = None;
+        if let Some(ref mut object) = frame.object {
+            run_object(&mut **object, RunContext {
+                id,
+                tx,
+                outcome: &mut outcome,
+                args,
+                errors,
+                token: self.token.clone(),
+                restart: self.restart,
+                updaters: Arc::new(()),
+            });
         }
+        return task_loop.complete(system, id, self, outcome.unwrap_or(Outcome::Finished(None)));
     }
     // Executed by TaskLoop
-    fn update(&mut self, update: Update) {
-        unsafe {
-            match (*self.frame).object {
-                Some(object) => {
-                    (*object).update(update);
-                }
-                None => unimplemented!(),
-            }
+    fn update(
+        &self,
+        system: &mut System,
+        task_loop: &mut TaskLoop,
+        update: Update,
+    ) -> Result<(), Error> {
+        let frame = match system.get_mut(self.frame) {
+            Some(frame) => frame,
+            // The frame was removed while working in the background.
+            None => return Ok(()),
+        };
+        if let Some(ref mut object) = frame.object {
+            object.update(update);
+            return Ok(());
         }
+        return system.report(self.frame, Error::EmptyFrame(self.frame), task_loop);
     }
     // Executed by TaskLoop
-    fn finish(self, task_loop: &mut TaskLoop) {
-        unsafe {
-            (*self.frame).running = false;
-            let system = (*self.frame).parent_system().unwrap();
-            system.run_finished(self.frame, task_loop);
+    fn finish(
+        self,
+        system: &mut System,
+        task_loop: &mut TaskLoop,
+        id: u64,
+        error: Option<Error>,
+    ) -> Result<(), Error> {
+        if let Some(frame) = system.get_mut(self.frame) {
+            frame.running = false;
+        }
+        task_loop.trace(TraceEvent::Finish {
+            frame: self.frame,
+            task: id,
+            error: error.clone(),
+        });
+        match error {
+            Some(error) => system.run_failed(self.frame, error, task_loop),
+            None => system.run_finished(self.frame, task_loop),
         }
     }
 }
 
-fn alloc<T>(val: T) -> *mut T {
-    Box::into_raw(Box::new(val))
-}
-
 impl Frame {
-    fn schedule(&mut self, task_loop: &mut TaskLoop) {
+    pub fn id(&self) -> FrameId {
+        self.id
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn object(&self) -> Option<&dyn Object> {
+        self.object.as_deref()
+    }
+    // Scheduling a frame holding a system schedules the entry frames of that
+    // system instead. Fails with `Error::CannotRun` if the object can't run,
+    // or is a system without entry frames.
+    fn schedule(&mut self, task_loop: &mut TaskLoop) -> Result<(), Error> {
+        let id = self.id;
+        if let Some(system) = self.system_mut() {
+            if system.entries.is_empty() {
+                return Err(Error::CannotRun(id));
+            }
+            let mut result = Ok(());
+            for entry in system.entries.clone() {
+                if let Some(frame) = system.own_frame_mut(entry) {
+                    result = result.and(frame.schedule(task_loop));
+                }
+            }
+            return result;
+        }
+        if self.object.as_ref().is_some_and(|object| !object.can_run()) {
+            return Err(Error::CannotRun(id));
+        }
         if !self.scheduled {
             self.scheduled = true;
-            task_loop.post(Task { frame: self });
+            task_loop.post(Task {
+                frame: self.id,
+                token: CancelToken::default(),
+                restart: false,
+            });
+            task_loop.trace(TraceEvent::Schedule { frame: self.id });
         }
-    }
-
-    pub fn adopt(&mut self, adopted: Option<Box<Object>>) {
-        self.object = adopted.map(Box::into_raw);
-        self.maybe_update_frame();
-    }
-    fn maybe_update_frame(&mut self) {
-        let object = self.object;
-        if let Some(object) = object {
-            unsafe {
-                if let ConcreteObject::System(system) = (*object).concrete() {
-                    (*system).frame = Some(self);
+        return Ok(());
+    }
+    // Replaces the object held by this frame, returning the previous one.
+    // Outside the crate, objects are replaced through `System::adopt`, so that
+    // the edit can be undone.
+    pub(crate) fn adopt(&mut self, adopted: Option<Box<dyn Object>>) -> Option<Box<dyn Object>> {
+        mem::replace(&mut self.object, adopted)
+    }
+    fn system_mut(&mut self) -> Option<&mut System> {
+        match self.object {
+            Some(ref mut object) => {
+                match object.concrete() {
+                    ConcreteObject::System(system) => Some(system),
+                    _ => None,
                 }
             }
+            None => None,
         }
     }
-    fn parent_system(&self) -> Option<&'static mut System> {
-        return System::from_object(self.parent);
-    }
-    fn find_element(&mut self, name: &String) -> Option<*mut Frame> {
-        if let Some(object) = self.object {
-            return find_element(object, name);
-        }
-        return None;
+    // Slash-separated names of the frames leading from `system` down to this
+    // frame.
+    pub fn path(&self, system: &System) -> Option<String> {
+        system.element_path(self.id).map(|path| path.join("/"))
     }
-    pub fn swap(a: *mut Frame, b: *mut Frame) {
-        unsafe {
-            if let Some(a) = System::from_frame(a) {
-                a.break_links();
-            }
-            if let Some(b) = System::from_frame(b) {
-                b.break_links();
-            }
-            mem::swap(&mut (*a).object, &mut (*b).object);
-            (*a).maybe_update_frame();
-            (*b).maybe_update_frame();
-            if let Some(a) = System::from_frame(a) {
-                a.fix_links();
-            }
-            if let Some(b) = System::from_frame(b) {
-                b.fix_links();
-            }
-        }
+    fn resolve(&self, path: &[String]) -> Option<FrameId> {
+        resolve_element_path(self.object()?, path)
     }
 }
 
 impl System {
     pub fn new() -> Box<System> {
         Box::new(System {
-            frame: None,
             frames: Vec::new(),
+            free: Vec::new(),
             links: Vec::new(),
+            error_handler: None,
+            entries: Vec::new(),
+            outputs: Vec::new(),
+            paths: RefCell::new(HashMap::new()),
+            history: Vec::new(),
+            undone: Vec::new(),
         })
     }
-    fn from_object(object: *mut Object) -> Option<&'static mut System> {
-        unsafe {
-            match (*object).concrete() {
-                ConcreteObject::System(system) => return Some(&mut *system),
-                _ => None,
+    // Looks up a frame anywhere in this system or its subsystems.
+    pub fn get(&self, id: FrameId) -> Option<&Frame> {
+        let path = self.path(id)?;
+        return self.subsystem(&path[..path.len() - 1])?.own_frame(id);
+    }
+    pub fn get_mut(&mut self, id: FrameId) -> Option<&mut Frame> {
+        let path = self.path(id)?;
+        return self.subsystem_mut(&path[..path.len() - 1])?.own_frame_mut(id);
+    }
+    fn own_frame(&self, id: FrameId) -> Option<&Frame> {
+        self.frames
+            .get(id.index)
+            .and_then(|slot| slot.as_ref())
+            .filter(|frame| frame.id == id)
+    }
+    fn own_frame_mut(&mut self, id: FrameId) -> Option<&mut Frame> {
+        self.frames
+            .get_mut(id.index)
+            .and_then(|slot| slot.as_mut())
+            .filter(|frame| frame.id == id)
+    }
+    // Frames leading from this system down to `id`, including `id` itself.
+    // Only frames of subsystems are searched for, and only the first time.
+    fn path(&self, id: FrameId) -> Option<Vec<FrameId>> {
+        if self.own_frame(id).is_some() {
+            return Some(vec![id]);
+        }
+        let mut paths = self.paths.borrow_mut();
+        if let Some(path) = paths.get(&id) {
+            let parent = self.subsystem(&path[..path.len() - 1]);
+            if parent.is_some_and(|parent| parent.own_frame(id).is_some()) {
+                return Some(path.clone());
             }
         }
-    }
-    fn from_frame(frame: *mut Frame) -> Option<&'static mut System> {
-        unsafe {
-            if let Some(object) = (*frame).object {
-                System::from_object(object)
-            } else {
+        match find_path(self, id) {
+            Some(path) => {
+                paths.insert(id, path.clone());
+                Some(path)
+            }
+            None => {
+                paths.remove(&id);
                 None
             }
         }
     }
-    fn parent_system(&self) -> Option<&'static mut System> {
-        if let Some(my_frame) = self.frame {
-            unsafe {
-                return (*my_frame).parent_system();
-            }
+    fn subsystem(&self, path: &[FrameId]) -> Option<&System> {
+        let mut system = self;
+        for &id in path {
+            system = system.own_frame(id)?.object()?.system()?;
         }
-        return None;
+        return Some(system);
     }
-    fn pick_name(&self, frame: *mut Frame) {
-        let object = unsafe { (*frame).object };
-        let base = match object {
-            Some(object) => unsafe { (*object).name().to_string() },
+    fn subsystem_mut(&mut self, path: &[FrameId]) -> Option<&mut System> {
+        let mut system = self;
+        for &id in path {
+            system = system.own_frame_mut(id)?.system_mut()?;
+        }
+        return Some(system);
+    }
+    // Links of every system enclosing `frame`, innermost first.
+    fn enclosing_links(&self, frame: FrameId) -> impl Iterator<Item = &Link> {
+        let path = self.path(frame).unwrap_or_default();
+        let systems: Vec<&System> =
+            (0..path.len()).rev().filter_map(|depth| self.subsystem(&path[..depth])).collect();
+        return systems.into_iter().flat_map(|system| system.links.iter());
+    }
+    // Finds a frame by a slash-separated path of names, like
+    // `Left/System2/MockObject`.
+    pub fn resolve(&self, path: &str) -> Option<FrameId> {
+        let path: Vec<String> = path.split('/').map(|name| name.to_string()).collect();
+        return resolve_element_path(self, &path);
+    }
+    fn has_name(&self, name: &str) -> bool {
+        self.frames.iter().flatten().any(|frame| frame.name == name)
+    }
+    // Names only need to be unique among the frames of one system.
+    fn pick_name(&self, object: &Option<Box<dyn Object>>) -> String {
+        let base = match *object {
+            Some(ref object) => object.name().to_string(),
             None => "Frame".to_string(),
         };
-        let name = if let Some(_) = find_element(self, &base) {
+        if self.has_name(&base) {
             let mut counter = 2;
             let mut candidate = base.clone() + &counter.to_string();
-            while let Some(_) = find_element(self, &candidate) {
+            while self.has_name(&candidate) {
                 counter += 1;
                 candidate = base.clone() + &counter.to_string();
             }
             candidate
         } else {
             base
-        };
-        unsafe {
-            (*frame).name = name;
         }
     }
-    pub fn frame(&mut self, object: Option<Box<Object>>) -> &'static mut Frame {
-        let frame = alloc(Frame {
-            parent: self,
-            name: String::new(),
-            object: object.map(Box::into_raw),
+    // Renames a frame anywhere in this system. The name must be unique among
+    // the frames of its parent system. Links referring to the frame by path
+    // are updated.
+    pub fn rename(&mut self, frame: FrameId, name: &str) -> Result<(), Error> {
+        if name.is_empty() || name.contains('/') {
+            return Err(Error::InvalidName(name.to_string()));
+        }
+        let path = self.path(frame).ok_or(Error::DanglingFrame(frame))?;
+        let taken = self.subsystem_mut(&path[..path.len() - 1]).is_some_and(|parent| {
+            parent.frames.iter().flatten().any(|other| other.name == name && other.id != frame)
+        });
+        if taken {
+            return Err(Error::NameTaken(name.to_string()));
+        }
+        let old = self.rename_frame(frame, name);
+        self.record(Edit::Rename(frame, old));
+        return Ok(());
+    }
+    // Returns the previous name.
+    fn rename_frame(&mut self, frame: FrameId, name: &str) -> String {
+        self.update_enclosing_links(frame, |system, link| {
+            system.rename_link_end(&mut link.a, frame, name);
+            system.rename_link_end(&mut link.b, frame, name);
+        });
+        return mem::replace(&mut self.get_mut(frame).unwrap().name, name.to_string());
+    }
+    // Replaces the name of `frame` in a path that leads through it.
+    fn rename_link_end(&self, link_end: &mut LinkEnd, frame: FrameId, name: &str) {
+        if let LinkEnd::FrameElement(holder, ref mut path) = *link_end {
+            let mut object = self.get(holder).and_then(|holder| holder.object());
+            for element in path.iter_mut() {
+                let found = object.and_then(|object| {
+                    object.elements().into_iter().find(|frame| frame.name == *element)
+                });
+                let found = match found {
+                    Some(found) => found,
+                    None => return,
+                };
+                if found.id == frame {
+                    *element = name.to_string();
+                    return;
+                }
+                object = found.object();
+            }
+        }
+    }
+    pub fn frame(&mut self, object: Option<Box<dyn Object>>) -> FrameId {
+        let name = self.pick_name(&object);
+        let id = self.insert(name, object);
+        self.record(Edit::Frame(id, None));
+        return id;
+    }
+    // Adds a frame to the system held by `holder`, anywhere in this system.
+    // The edit is kept in the history of this system rather than that of the
+    // subsystem, so that undoing it can also drop the links reaching the
+    // frame from enclosing systems. Returns None if `holder` doesn't hold a system.
+    pub fn frame_in(&mut self, holder: FrameId, object: Option<Box<dyn Object>>) -> Option<FrameId> {
+        let system = self.get_mut(holder)?.system_mut()?;
+        let name = system.pick_name(&object);
+        let id = system.insert(name, object);
+        self.record(Edit::Frame(id, None));
+        return Some(id);
+    }
+    fn insert(&mut self, name: String, object: Option<Box<dyn Object>>) -> FrameId {
+        let id = FrameId {
+            index: self.free.pop().unwrap_or(self.frames.len()),
+            generation: GENERATION.fetch_add(1, Ordering::Relaxed),
+        };
+        let frame = Frame {
+            id,
+            name,
+            object,
             running: false,
+            away: false,
             scheduled: false,
-        });
-        self.pick_name(frame);
-        unsafe {
-            (*frame).maybe_update_frame();
+            errors: Vec::new(),
+            arrived: HashSet::new(),
+        };
+        if id.index == self.frames.len() {
+            self.frames.push(Some(frame));
+        } else {
+            self.frames[id.index] = Some(frame);
+        }
+        return id;
+    }
+    // Removes a frame from anywhere in this system together with the links
+    // that reach into it and its pending runs, returning the object it held.
+    // As the object is handed back, the removal isn't recorded and can't be
+    // undone. Undoing earlier edits of the removed frames does nothing.
+    // Frames whose objects, or objects inside them, are on a worker aren't
+    // removed.
+    pub fn remove_frame(&mut self, frame: FrameId, task_loop: &mut TaskLoop) -> Option<Box<dyn Object>> {
+        if !self.holds_object(frame) {
+            return None;
         }
-        self.frames.push(frame);
-        return unsafe { &mut *frame };
+        return self.take_frame(frame, task_loop).and_then(|taken| taken.frame.object);
     }
-    fn run_finished(&mut self, frame: *mut Frame, task_loop: &mut TaskLoop) {
-        for link in self.links.clone().into_iter() {
-            match link.relation {
-                Relation::Then => {}
-                _ => continue,
+    // Frames that can't run are reported instead of being scheduled.
+    fn schedule(&mut self, frame: FrameId, task_loop: &mut TaskLoop) -> Result<(), Error> {
+        let result = match self.get_mut(frame) {
+            Some(frame) => frame.schedule(task_loop),
+            None => return Ok(()),
+        };
+        if let Err(error) = result {
+            return self.report(frame, error, task_loop);
+        }
+        return Ok(());
+    }
+    fn collect_args(&mut self, frame: FrameId) -> HashMap<String, Value> {
+        let mut args = HashMap::new();
+        for source in self.predecessors(frame, Relation::Arg) {
+            if let Some(source) = self.get(source) {
+                if let Some(ref object) = source.object {
+                    if let Some(output) = object.output() {
+                        args.insert(source.name.clone(), output);
+                    }
+                }
+            }
+        }
+        return args;
+    }
+    // Errors about `frame` go to the error handler of the innermost system
+    // enclosing it that has one. Errors with no handler are returned.
+    pub fn set_error_handler(&mut self, handler: Option<FrameId>) {
+        self.error_handler = handler;
+    }
+    // Marks a frame of this system as one to run when a frame holding this
+    // system runs. Links from the holding frame can then lead to the system
+    // itself rather than into it.
+    pub fn set_entry(&mut self, frame: FrameId, entry: bool) {
+        self.entries.retain(|&other| other != frame);
+        if entry {
+            self.entries.push(frame);
+        }
+    }
+    pub fn entries(&self) -> &[FrameId] {
+        &self.entries
+    }
+    fn report(&mut self, frame: FrameId, error: Error, task_loop: &mut TaskLoop) -> Result<(), Error> {
+        let path = match self.path(frame) {
+            Some(path) => path,
+            None => return Err(error),
+        };
+        for depth in (0..path.len()).rev() {
+            let handler = self.subsystem_mut(&path[..depth]).and_then(|system| system.error_handler);
+            // A failing handler reports to the handler of an outer system.
+            if let Some(handler) = handler.filter(|handler| *handler != frame) {
+                if let Some(frame) = self.get_mut(handler) {
+                    frame.errors.push(error);
+                    return self.schedule(handler, task_loop);
+                }
+            }
+        }
+        return Err(error);
+    }
+    // Frames linked from `frame` with `relation`.
+    fn successors(&self, frame: FrameId, relation: Relation) -> Vec<Result<FrameId, Error>> {
+        let mut successors = Vec::new();
+        for link in self.enclosing_links(frame) {
+            if link.relation != relation {
+                continue;
             }
             match link.a {
                 LinkEnd::Frame(link_a) => {
@@ -398,126 +1286,526 @@ impl System {
                 }
                 _ => continue,
             }
-            match link.b {
-                LinkEnd::Frame(link_b) => unsafe {
-                    (*link_b).schedule(task_loop);
-                },
-                LinkEnd::FrameElement(frame, element) => unsafe {
-                    let target = (*frame).find_element(&element);
-                    match target {
-                        Some(frame) => {
-                            (*frame).schedule(task_loop);
-                        }
-                        None => {
-                            panic!("Element {} not found", element);
-                        }
+            let target = match link.b {
+                LinkEnd::Frame(link_b) => {
+                    match self.get(link_b) {
+                        Some(_) => Ok(link_b),
+                        None => Err(Error::DanglingFrame(link_b)),
                     }
-                },
-            }
+                }
+                LinkEnd::FrameElement(element_frame, ref element) => {
+                    let target = self.get(element_frame).and_then(|element_frame| {
+                        element_frame.resolve(element)
+                    });
+                    target.ok_or(Error::ElementNotFound(element.join("/")))
+                }
+            };
+            successors.push(target);
         }
-        if let Some(parent) = self.parent_system() {
-            parent.run_finished(frame, task_loop);
+        return successors;
+    }
+    // Frames linked to `frame` with `relation`.
+    fn predecessors(&self, frame: FrameId, relation: Relation) -> Vec<FrameId> {
+        let mut predecessors = Vec::new();
+        for link in self.enclosing_links(frame) {
+            if link.relation != relation || link.b.resolve(self) != Some(frame) {
+                continue;
+            }
+            if let Some(source) = link.a.resolve(self) {
+                predecessors.push(source);
+            }
         }
-    }
-    fn contains(&self, frame: *mut Frame) -> bool {
-        unsafe {
-            if let Some(other) = (*frame).parent_system() {
-                if ptr::eq(other, self) {
-                    return true;
+        return predecessors;
+    }
+    fn run_finished(&mut self, frame: FrameId, task_loop: &mut TaskLoop) -> Result<(), Error> {
+        let mut result = Ok(());
+        for target in self.successors(frame, Relation::Then) {
+            match target {
+                Ok(target) => {
+                    task_loop.trace(TraceEvent::Traverse {
+                        from: frame,
+                        to: target,
+                        relation: Relation::Then,
+                    });
+                    result = result.and(self.schedule(target, task_loop));
                 }
-                if let Some(higher_frame) = other.frame {
-                    return self.contains(higher_frame);
+                Err(error) => result = result.and(self.report(frame, error, task_loop)),
+            }
+        }
+        for target in self.successors(frame, Relation::After) {
+            match target {
+                Ok(target) => {
+                    task_loop.trace(TraceEvent::Traverse {
+                        from: frame,
+                        to: target,
+                        relation: Relation::After,
+                    });
+                    result = result.and(self.join(target, frame, task_loop));
                 }
+                Err(error) => result = result.and(self.report(frame, error, task_loop)),
             }
-            return false;
         }
-    }
-    fn break_link_end(&mut self, link_end: &mut LinkEnd) {
-        let frame = link_end.frame();
-        if self.contains(frame) {
-            println!("Cutting a link!");
-            let name: String = unsafe { (*frame).name.clone() };
-            *link_end = LinkEnd::FrameElement(self.frame.unwrap(), name);
+        return result.and(self.leave(frame, task_loop));
+    }
+    // Schedules `target` once every one of its `After` predecessors arrived.
+    fn join(&mut self, target: FrameId, arrived: FrameId, task_loop: &mut TaskLoop) -> Result<(), Error> {
+        let predecessors = self.predecessors(target, Relation::After);
+        if let Some(frame) = self.get_mut(target) {
+            frame.arrived.insert(arrived);
+            if predecessors.iter().all(|predecessor| frame.arrived.contains(predecessor)) {
+                frame.arrived.clear();
+                return self.schedule(target, task_loop);
+            }
         }
+        return Ok(());
     }
-    fn break_link(&mut self, link: &mut Link) {
-        self.break_link_end(&mut link.a);
-        self.break_link_end(&mut link.b);
-    }
-    fn break_links(&mut self) {
-        let mut frame = self.frame.unwrap();
-        unsafe {
-            while let Some(mut parent) = (*frame).parent_system() {
-                for mut link in parent.links.iter_mut() {
-                    self.break_link(link);
-                }
-                if let Some(x) = parent.frame {
-                    frame = x;
-                } else {
-                    break;
+    fn run_failed(&mut self, frame: FrameId, error: Error, task_loop: &mut TaskLoop) -> Result<(), Error> {
+        let handlers = self.successors(frame, Relation::Catch);
+        if handlers.is_empty() {
+            return self.report(frame, error, task_loop);
+        }
+        let mut result = Ok(());
+        for target in handlers {
+            match target {
+                Ok(target) => {
+                    task_loop.trace(TraceEvent::Traverse {
+                        from: frame,
+                        to: target,
+                        relation: Relation::Catch,
+                    });
+                    if let Some(handler) = self.get_mut(target) {
+                        handler.errors.push(error.clone());
+                        result = result.and(self.schedule(target, task_loop));
+                    }
                 }
+                Err(link_error) => result = result.and(self.report(frame, link_error, task_loop)),
+            }
+        }
+        return result;
+    }
+    fn break_link_end(
+        link_end: &mut LinkEnd,
+        frame: FrameId,
+        inner: &HashMap<FrameId, Vec<String>>,
+        events: &mut Vec<TraceEvent>,
+    ) {
+        if let LinkEnd::Frame(target) = *link_end {
+            if let Some(path) = inner.get(&target) {
+                events.push(TraceEvent::LinkCut {
+                    holder: frame,
+                    path: path.clone(),
+                });
+                *link_end = LinkEnd::FrameElement(frame, path.clone());
             }
         }
     }
-    fn fix_link_end(&mut self, link_end: &mut LinkEnd) {
-        let my_frame = self.frame.unwrap();
-        if let LinkEnd::FrameElement(frame, element) = link_end.clone() {
-            if ptr::eq(frame, my_frame) {
-                let target = unsafe { (*my_frame).find_element(&element) };
+    fn break_link(
+        link: &mut Link,
+        frame: FrameId,
+        inner: &HashMap<FrameId, Vec<String>>,
+        events: &mut Vec<TraceEvent>,
+    ) {
+        System::break_link_end(&mut link.a, frame, inner, events);
+        System::break_link_end(&mut link.b, frame, inner, events);
+    }
+    // Turns links pointing into the object held by `frame` into path references.
+    fn break_links(&mut self, frame: FrameId, task_loop: &mut TaskLoop) {
+        let mut inner = HashMap::new();
+        if let Some(object) = self.get(frame).and_then(|frame| frame.object()) {
+            find_descendants(object, &[], &mut inner);
+        }
+        if inner.is_empty() {
+            return;
+        }
+        let mut events = Vec::new();
+        self.update_enclosing_links(frame, |_, link| {
+            System::break_link(link, frame, &inner, &mut events)
+        });
+        for event in events {
+            task_loop.trace(event);
+        }
+    }
+    fn fix_link_end(&self, link_end: &mut LinkEnd, frame: FrameId, events: &mut Vec<TraceEvent>) {
+        if let LinkEnd::FrameElement(element_frame, ref element) = link_end.clone() {
+            if element_frame == frame {
+                let target = self.get(frame).and_then(|frame| frame.resolve(element));
                 if let Some(target) = target {
+                    events.push(TraceEvent::LinkFix {
+                        holder: frame,
+                        path: element.clone(),
+                        frame: target,
+                    });
                     *link_end = LinkEnd::Frame(target);
                 }
             }
         }
     }
-    fn fix_link(&mut self, link: &mut Link) {
-        self.fix_link_end(&mut link.a);
-        self.fix_link_end(&mut link.b);
+    fn fix_link(&self, link: &mut Link, frame: FrameId, events: &mut Vec<TraceEvent>) {
+        self.fix_link_end(&mut link.a, frame, events);
+        self.fix_link_end(&mut link.b, frame, events);
     }
-    fn fix_links(&mut self) {
-        let mut frame = self.frame.unwrap();
-        unsafe {
-            while let Some(mut parent) = (*frame).parent_system() {
-                for mut link in parent.links.iter_mut() {
-                    self.fix_link(link);
-                }
-                if let Some(x) = parent.frame {
-                    frame = x;
-                } else {
-                    break;
-                }
+    // Resolves path references into the object held by `frame` back into links.
+    fn fix_links(&mut self, frame: FrameId, task_loop: &mut TaskLoop) {
+        let mut events = Vec::new();
+        self.update_enclosing_links(frame, |system, link| system.fix_link(link, frame, &mut events));
+        for event in events {
+            task_loop.trace(event);
+        }
+    }
+    fn update_enclosing_links<F>(&mut self, frame: FrameId, mut f: F)
+    where
+        F: FnMut(&System, &mut Link),
+    {
+        let path = match self.path(frame) {
+            Some(path) => path,
+            None => return,
+        };
+        for depth in 0..path.len() {
+            let mut links = match self.subsystem_mut(&path[..depth]) {
+                Some(system) => mem::take(&mut system.links),
+                None => continue,
+            };
+            for link in links.iter_mut() {
+                f(self, link);
+            }
+            if let Some(system) = self.subsystem_mut(&path[..depth]) {
+                system.links = links;
             }
         }
-
     }
-    pub fn link(&mut self, a: *mut Frame, b: *mut Frame, relation: Relation) {
-        self.links.push(Link {
+    // Exchanges the objects held by two frames anywhere in this system.
+    // Background work of either frame is cancelled, as its updates would
+    // reach the other object. Returns false if either frame doesn't exist or
+    // its object, or one inside it, is running on a worker.
+    pub fn swap(&mut self, a: FrameId, b: FrameId, task_loop: &mut TaskLoop) -> bool {
+        if !self.holds_object(a) || !self.holds_object(b) {
+            return false;
+        }
+        self.swap_objects(a, b, task_loop);
+        self.record(Edit::Swap(a, b));
+        return true;
+    }
+    // Whether the object of `frame` is at hand to be replaced, along with the
+    // objects of the frames inside it.
+    fn holds_object(&self, frame: FrameId) -> bool {
+        self.get(frame).is_some_and(|frame| !frame.away && !frame.object().is_some_and(away_inside))
+    }
+    fn swap_objects(&mut self, a: FrameId, b: FrameId, task_loop: &mut TaskLoop) {
+        self.exchange(a, b, task_loop);
+        self.fix_links(a, task_loop);
+        self.fix_links(b, task_loop);
+    }
+    // Swaps the objects, leaving links into them as path references.
+    fn exchange(&mut self, a: FrameId, b: FrameId, task_loop: &mut TaskLoop) {
+        task_loop.cancel(self, a);
+        task_loop.cancel(self, b);
+        self.break_links(a, task_loop);
+        self.break_links(b, task_loop);
+        let object_a = self.get_mut(a).unwrap().object.take();
+        let object_b = self.get_mut(b).unwrap().adopt(object_a);
+        self.get_mut(a).unwrap().adopt(object_b);
+    }
+    pub fn link(&mut self, a: FrameId, b: FrameId, relation: Relation) {
+        let link = Link {
             relation,
             a: LinkEnd::Frame(a),
             b: LinkEnd::Frame(b),
-        });
-    }
-}
-
-impl Object for System {
-    fn name(&self) -> &'static str {
-        "System"
-    }
-    fn concrete(&mut self) -> ConcreteObject {
-        ConcreteObject::System(self)
-    }
-    fn elements(&self) -> &[*mut Frame] {
-        &self.frames
+        };
+        self.links.push(link.clone());
+        self.record(Edit::Link(link, true));
+    }
+    // Replaces the object held by `frame`. The replaced object stays in the
+    // edit history so that `undo` can bring it back. Returns false if there is
+    // no such frame or its object, or one inside it, is running on a worker.
+    pub fn adopt(&mut self, frame: FrameId, object: Option<Box<dyn Object>>) -> bool {
+        if !self.holds_object(frame) {
+            return false;
+        }
+        let replaced = self.get_mut(frame).unwrap().adopt(object);
+        self.record(Edit::Adopt(frame, replaced));
+        return true;
+    }
+    // Removes the links of this system between `a` and `b`. Returns false if
+    // there were none.
+    pub fn unlink(&mut self, a: FrameId, b: FrameId, relation: Relation) -> bool {
+        self.remove_links(|system, link| {
+            link.relation == relation && link.a.resolve(system) == Some(a) &&
+                link.b.resolve(system) == Some(b)
+        })
     }
-}
-
+    // Removes the links of this system matching `f`, recording them so that
+    // `undo` can put them back where they were.
+    fn remove_links<F>(&mut self, f: F) -> bool
+    where
+        F: Fn(&System, &Link) -> bool,
+    {
+        let mut removed = Vec::new();
+        let mut kept = Vec::new();
+        for (position, link) in mem::take(&mut self.links).into_iter().enumerate() {
+            if f(self, &link) {
+                removed.push((position, link));
+            } else {
+                kept.push(link);
+            }
+        }
+        self.links = kept;
+        if removed.is_empty() {
+            return false;
+        }
+        self.record(Edit::Unlink(removed, true));
+        return true;
+    }
+    // Links of this system matching the given endpoints and relation. Links
+    // with an end that can't be resolved are skipped, and listed by
+    // `unresolved_links` instead.
+    pub fn find_links(
+        &self,
+        a: Option<FrameId>,
+        b: Option<FrameId>,
+        relation: Option<Relation>,
+    ) -> Vec<(FrameId, FrameId, Relation)> {
+        let mut found = Vec::new();
+        for link in self.links.iter() {
+            if let (Some(link_a), Some(link_b)) = (link.a.resolve(self), link.b.resolve(self)) {
+                if a.is_none_or(|a| a == link_a) && b.is_none_or(|b| b == link_b) &&
+                    relation.is_none_or(|relation| relation == link.relation)
+                {
+                    found.push((link_a, link_b, link.relation));
+                }
+            }
+        }
+        return found;
+    }
+    // Ends of the links of this system that can't be resolved, as the frame
+    // they lead from and the path of names they lead to inside it. Ends
+    // referring to a removed frame have an empty path.
+    pub fn unresolved_links(&self) -> Vec<(FrameId, Vec<String>, Relation)> {
+        let mut found = Vec::new();
+        for link in self.links.iter() {
+            for end in [&link.a, &link.b] {
+                if !end.resolves(self) {
+                    found.push((end.frame(), end.element().to_vec(), link.relation));
+                }
+            }
+        }
+        return found;
+    }
+    // Removes the links of this system with an end that can't be resolved
+    // and leads to `path` inside `frame`. Returns false if there were none.
+    pub fn unlink_unresolved(&mut self, frame: FrameId, path: &[String]) -> bool {
+        self.remove_links(|system, link| {
+            [&link.a, &link.b].into_iter().any(|end| {
+                !end.resolves(system) && end.frame() == frame && end.element() == path
+            })
+        })
+    }
+}
+
+impl Relation {
+    fn tag(self) -> u8 {
+        match self {
+            Relation::Then => 0,
+            Relation::Arg => 1,
+            Relation::Catch => 2,
+            Relation::After => 3,
+        }
+    }
+    fn from_tag(tag: u8) -> Result<Relation, DecodeError> {
+        match tag {
+            0 => Ok(Relation::Then),
+            1 => Ok(Relation::Arg),
+            2 => Ok(Relation::Catch),
+            3 => Ok(Relation::After),
+            _ => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+// Serialization
+impl System {
+    // Restores a system saved with `serialize`. Objects held by frames are
+    // constructed through `registry`.
+    pub fn load(bytes: &[u8], registry: &ObjectRegistry) -> Result<Box<System>, DecodeError> {
+        let mut system = System::new();
+        system.decode(bytes, registry)?;
+        return Ok(system);
+    }
+    // Names of the frames leading from this system down to `frame`.
+    fn element_path(&self, frame: FrameId) -> Option<Vec<String>> {
+        let path = self.path(frame)?;
+        return path.iter().map(|id| self.get(*id).map(|frame| frame.name.clone())).collect();
+    }
+    fn encode_path(&self, out: &mut Vec<u8>, frame: FrameId) -> bool {
+        match self.element_path(frame) {
+            Some(path) => {
+                encoding::write_names(out, &path);
+                true
+            }
+            None => false,
+        }
+    }
+    fn encode_link_end(&self, out: &mut Vec<u8>, link_end: &LinkEnd) -> bool {
+        match *link_end {
+            LinkEnd::Frame(frame) => {
+                encoding::write_u8(out, 0);
+                self.encode_path(out, frame)
+            }
+            LinkEnd::FrameElement(frame, ref element) => {
+                encoding::write_u8(out, 1);
+                if !self.encode_path(out, frame) {
+                    return false;
+                }
+                encoding::write_names(out, element);
+                true
+            }
+        }
+    }
+    fn decode_path(&self, reader: &mut Reader) -> Result<FrameId, DecodeError> {
+        let path = reader.read_names()?;
+        return resolve_element_path(self, &path).ok_or(DecodeError::UnresolvedPath(path));
+    }
+    fn decode_link_end(&self, reader: &mut Reader) -> Result<LinkEnd, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(LinkEnd::Frame(self.decode_path(reader)?)),
+            1 => {
+                let frame = self.decode_path(reader)?;
+                Ok(LinkEnd::FrameElement(frame, reader.read_names()?))
+            }
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+    fn decode(&mut self, bytes: &[u8], registry: &ObjectRegistry) -> Result<(), DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let version = reader.read_u32()?;
+        if version != FORMAT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        for _ in 0..reader.read_u32()? {
+            let name = reader.read_string()?;
+            let object = if reader.read_u8()? != 0 {
+                let type_name = reader.read_string()?;
+                let payload = reader.read_bytes()?;
+                let mut object = registry.create(&type_name)?;
+                match object.concrete() {
+                    ConcreteObject::System(system) => system.decode(&payload, registry)?,
+                    ConcreteObject::Other(object) => object.deserialize(payload),
+                }
+                Some(object)
+            } else {
+                None
+            };
+            self.insert(name, object);
+        }
+        for _ in 0..reader.read_u32()? {
+            let relation = Relation::from_tag(reader.read_u8()?)?;
+            let a = self.decode_link_end(&mut reader)?;
+            let b = self.decode_link_end(&mut reader)?;
+            self.links.push(Link { relation, a, b });
+        }
+        if reader.read_u8()? != 0 {
+            self.error_handler = Some(self.decode_path(&mut reader)?);
+        }
+        for _ in 0..reader.read_u32()? {
+            let entry = self.decode_path(&mut reader)?;
+            self.entries.push(entry);
+        }
+        for _ in 0..reader.read_u32()? {
+            let output = self.decode_path(&mut reader)?;
+            self.outputs.push(output);
+        }
+        return Ok(());
+    }
+}
+
+impl Object for System {
+    fn name(&self) -> &'static str {
+        "System"
+    }
+    fn concrete(&mut self) -> ConcreteObject<'_> {
+        ConcreteObject::System(self)
+    }
+    fn system(&self) -> Option<&System> {
+        Some(self)
+    }
+    // Frames are written with their names, the `Object::name` and serialized
+    // state of their objects. Link ends are written as paths of frame names.
+    fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encoding::write_u32(&mut out, FORMAT_VERSION);
+        let frames: Vec<&Frame> = self.elements();
+        encoding::write_u32(&mut out, frames.len() as u32);
+        for frame in frames {
+            encoding::write_str(&mut out, &frame.name);
+            match frame.object {
+                Some(ref object) => {
+                    encoding::write_u8(&mut out, 1);
+                    encoding::write_str(&mut out, object.name());
+                    encoding::write_bytes(&mut out, &object.serialize());
+                }
+                None => encoding::write_u8(&mut out, 0),
+            }
+        }
+        // Links with ends that no longer exist are dropped.
+        let mut links = Vec::new();
+        let mut count = 0;
+        for link in self.links.iter() {
+            let mut encoded = Vec::new();
+            encoding::write_u8(&mut encoded, link.relation.tag());
+            if self.encode_link_end(&mut encoded, &link.a) &&
+                self.encode_link_end(&mut encoded, &link.b)
+            {
+                links.extend(encoded);
+                count += 1;
+            }
+        }
+        encoding::write_u32(&mut out, count);
+        out.extend(links);
+        let mut handler = Vec::new();
+        match self.error_handler {
+            Some(frame) if self.encode_path(&mut handler, frame) => {
+                encoding::write_u8(&mut out, 1);
+                out.extend(handler);
+            }
+            _ => encoding::write_u8(&mut out, 0),
+        }
+        let mut entries = Vec::new();
+        let mut count = 0;
+        for &entry in self.entries.iter() {
+            if self.encode_path(&mut entries, entry) {
+                count += 1;
+            }
+        }
+        encoding::write_u32(&mut out, count);
+        out.extend(entries);
+        let mut outputs = Vec::new();
+        let mut count = 0;
+        for &output in self.outputs.iter() {
+            if self.encode_path(&mut outputs, output) {
+                count += 1;
+            }
+        }
+        encoding::write_u32(&mut out, count);
+        out.extend(outputs);
+        return out;
+    }
+    // Only nested systems can be restored here. Use `ObjectRegistry::load` for
+    // graphs holding other objects. The system is left unchanged on failure.
+    fn deserialize(&mut self, bytes: Vec<u8>) {
+        if let Ok(system) = ObjectRegistry::new().load(&bytes) {
+            *self = *system;
+        }
+    }
+    fn elements(&self) -> Vec<&Frame> {
+        self.frames.iter().flatten().collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::rc::Rc;
-    use std::cell::RefCell;
+    use std::sync::Mutex;
     use super::*;
 
-    type Log = Rc<RefCell<Vec<String>>>;
+    type Log = Arc<Mutex<Vec<String>>>;
 
     struct MockObject {
         name: String,
@@ -541,18 +1829,24 @@ mod tests {
             true
         }
         fn run(&mut self, _: RunContext) {
-            self.log.borrow_mut().push(self.name.clone());
+            self.log.lock().unwrap().push(self.name.clone());
         }
-        fn concrete(&mut self) -> ConcreteObject {
+        fn concrete(&mut self) -> ConcreteObject<'_> {
             ConcreteObject::Other(self)
         }
+        fn serialize(&self) -> Vec<u8> {
+            self.name.clone().into_bytes()
+        }
+        fn deserialize(&mut self, bytes: Vec<u8>) {
+            self.name = String::from_utf8(bytes).unwrap();
+        }
     }
 
     struct TestableSystem {
         system: Box<System>,
-        a: &'static mut Frame,
-        b: &'static mut Frame,
-        c: &'static mut Frame,
+        a: FrameId,
+        b: FrameId,
+        c: FrameId,
     }
 
     struct Test {
@@ -562,7 +1856,7 @@ mod tests {
 
     impl Test {
         fn new() -> Self {
-            let log = Rc::new(RefCell::new(Vec::new()));
+            let log = Arc::new(Mutex::new(Vec::new()));
             Test {
                 log,
                 task_loop: TaskLoop::new(),
@@ -577,34 +1871,38 @@ mod tests {
             return TestableSystem { system, a, b, c };
         }
         fn log(&self) -> String {
-            self.log.borrow().join(" ")
+            self.log.lock().unwrap().join(" ")
         }
     }
 
+    fn subsystem(system: &mut System, frame: FrameId) -> &mut System {
+        system.get_mut(frame).unwrap().system_mut().unwrap()
+    }
+
     #[test]
     fn run_nothing() {
         let mut test = Test::new();
-        test.make_system("");
-        test.task_loop.run_until_done();
+        let TestableSystem { mut system, .. } = test.make_system("");
+        test.task_loop.run_until_done(&mut system).unwrap();
         assert_eq!(test.log(), "");
     }
 
     #[test]
     fn run_one() {
         let mut test = Test::new();
-        let TestableSystem { a, .. } = test.make_system("");
-        a.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
+        let TestableSystem { mut system, a, .. } = test.make_system("");
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
         assert_eq!(test.log(), ":a");
     }
 
     #[test]
     fn swap() {
         let mut test = Test::new();
-        let TestableSystem { a, b, .. } = test.make_system("");
-        Frame::swap(a, b);
-        a.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
+        let TestableSystem { mut system, a, b, .. } = test.make_system("");
+        system.swap(a, b, &mut test.task_loop);
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
         assert_eq!(test.log(), ":b");
     }
 
@@ -613,8 +1911,8 @@ mod tests {
         let mut test = Test::new();
         let TestableSystem { mut system, a, b, .. } = test.make_system("");
         system.link(a, b, Relation::Then);
-        a.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
         assert_eq!(test.log(), ":a :b");
     }
 
@@ -623,8 +1921,8 @@ mod tests {
         let mut test = Test::new();
         let TestableSystem { mut system, a, .. } = test.make_system("");
         system.link(a, a, Relation::Then);
-        a.schedule(&mut test.task_loop);
-        test.task_loop.run_iterations(3);
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_iterations(&mut system, 3).unwrap();
         assert_eq!(test.log(), ":a :a :a");
     }
 
@@ -639,8 +1937,8 @@ mod tests {
         } = test.make_system("");
         system.link(a, b, Relation::Then);
         system.link(a, c, Relation::Then);
-        a.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
         assert_eq!(test.log(), ":a :b :c");
     }
 
@@ -655,9 +1953,9 @@ mod tests {
         } = test.make_system("");
         system.link(a, c, Relation::Then);
         system.link(b, c, Relation::Then);
-        a.schedule(&mut test.task_loop);
-        b.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
+        system.schedule(a, &mut test.task_loop).unwrap();
+        system.schedule(b, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
         assert_eq!(test.log(), ":a :b :c");
     }
 
@@ -672,27 +1970,343 @@ mod tests {
         } = test.make_system("");
         system.link(a, c, Relation::Then);
         system.link(b, c, Relation::Then);
-        a.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
-        b.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        system.schedule(b, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
         assert_eq!(test.log(), ":a :c :b :c");
     }
 
+    #[test]
+    fn join() {
+        let mut test = Test::new();
+        let TestableSystem {
+            mut system,
+            a,
+            b,
+            c,
+        } = test.make_system("");
+        system.link(a, c, Relation::After);
+        system.link(b, c, Relation::After);
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), ":a :a");
+        system.schedule(b, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), ":a :a :b :c");
+        system.schedule(b, &mut test.task_loop).unwrap();
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), ":a :a :b :c :b :a :c");
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn timers() {
+        let clock = MockClock::new();
+        let mut test = Test::new();
+        test.task_loop = TaskLoop::with_clock(Box::new(clock.clone()));
+        let TestableSystem {
+            mut system,
+            a,
+            b,
+            c,
+        } = test.make_system("");
+        test.task_loop.schedule_after(a, ms(10));
+        test.task_loop.schedule_at(b, ms(5));
+        let timer = test.task_loop.schedule_at(c, ms(7));
+        assert!(test.task_loop.cancel_timer(timer));
+        assert!(!test.task_loop.cancel_timer(timer));
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), ":b :a");
+        assert_eq!(clock.now(), ms(10));
+    }
+
+    #[test]
+    fn periodic_timer() {
+        let clock = MockClock::new();
+        let mut test = Test::new();
+        test.task_loop = TaskLoop::with_clock(Box::new(clock.clone()));
+        let TestableSystem {
+            mut system,
+            a,
+            b,
+            ..
+        } = test.make_system("");
+        let timer = test.task_loop.schedule_every(a, ms(10));
+        test.task_loop.schedule_every(b, ms(25));
+        while clock.now() < ms(50) {
+            test.task_loop.run_one(&mut system).unwrap();
+        }
+        assert_eq!(test.log(), ":a :a :b :a :a");
+        test.task_loop.cancel_timer(timer);
+        test.task_loop.unschedule(b);
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(clock.now(), ms(50));
+
+        // A period too short for the encoding of a `Snapshot` doesn't stall
+        // the loop.
+        let timer = test.task_loop.add_timer(clock.now(), a, Some(Duration::ZERO));
+        test.task_loop.run_iterations(&mut system, 2).unwrap();
+        assert!(test.task_loop.cancel_timer(timer));
+        assert_eq!(test.log(), ":a :a :b :a :a :a :a");
+    }
+
+    #[test]
+    #[should_panic(expected = "timer period must not be zero")]
+    fn zero_period() {
+        let mut test = Test::new();
+        let TestableSystem { a, .. } = test.make_system("");
+        test.task_loop.schedule_every(a, Duration::ZERO);
+    }
+
+    #[test]
+    fn exclusive_frames() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let slow = system.frame(Some(SlowObject::new(&test.log)));
+        system.schedule(slow, &mut test.task_loop).unwrap();
+        test.task_loop.run_one(&mut system).unwrap();
+        system.schedule(slow, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "start end start end");
+    }
+
+    // Logs whether another `MeetObject` was running at the same time.
+    struct MeetObject {
+        log: Log,
+        count: Arc<(Mutex<usize>, std::sync::Condvar)>,
+    }
+
+    impl Object for MeetObject {
+        fn name(&self) -> &'static str {
+            "Meet"
+        }
+        fn concrete(&mut self) -> ConcreteObject<'_> {
+            ConcreteObject::Other(self)
+        }
+        fn can_run(&self) -> bool {
+            true
+        }
+        fn run(&mut self, _: RunContext) {
+            let (ref count, ref condvar) = *self.count;
+            let mut count = count.lock().unwrap();
+            *count += 1;
+            condvar.notify_all();
+            let (count, _) = condvar
+                .wait_timeout_while(count, ms(1000), |count| *count < 2)
+                .unwrap();
+            self.log.lock().unwrap().push(format!("met={}", *count >= 2));
+        }
+    }
+
+    #[test]
+    fn workers() {
+        let mut test = Test::new();
+        test.task_loop.set_workers(2);
+        let TestableSystem {
+            mut system,
+            a,
+            b,
+            c,
+        } = test.make_system("");
+        let count = Arc::new((Mutex::new(0), std::sync::Condvar::new()));
+        let x = system.frame(Some(Box::new(MeetObject {
+            log: test.log.clone(),
+            count: count.clone(),
+        })));
+        let y = system.frame(Some(Box::new(MeetObject {
+            log: test.log.clone(),
+            count,
+        })));
+        let slow = system.frame(Some(SlowObject::new(&test.log)));
+        system.link(x, a, Relation::After);
+        system.link(y, a, Relation::After);
+        system.link(a, b, Relation::Then);
+        system.link(b, slow, Relation::Then);
+        system.link(slow, c, Relation::Then);
+        system.schedule(x, &mut test.task_loop).unwrap();
+        system.schedule(y, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "met=true met=true :a :b start end :c");
+    }
+
+    // Finishes its background work before `run` even returns.
+    struct EagerObject(Log);
+
+    impl Object for EagerObject {
+        fn name(&self) -> &'static str {
+            "Eager"
+        }
+        fn concrete(&mut self) -> ConcreteObject<'_> {
+            ConcreteObject::Other(self)
+        }
+        fn can_run(&self) -> bool {
+            true
+        }
+        fn run(&mut self, ctx: RunContext) {
+            use std::thread;
+            let mut background = ctx.background_for(self);
+            thread::spawn(move || background.send_update(()))
+                .join()
+                .unwrap();
+        }
+        fn update(&mut self, update: Update) {
+            self.dispatch(update);
+        }
+    }
+
+    impl Updatable for EagerObject {
+        type Update = ();
+        fn receive(&mut self, _: ()) {
+            self.0.lock().unwrap().push("update".to_string());
+        }
+    }
+
+    #[test]
+    fn workers_deferred_events() {
+        let mut test = Test::new();
+        test.task_loop.set_workers(1);
+        let mut system = System::new();
+        let eager = system.frame(Some(Box::new(EagerObject(test.log.clone()))));
+        let then = system.frame(Some(MockObject::new("then".to_string(), &test.log)));
+        system.link(eager, then, Relation::Then);
+        system.schedule(eager, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "update then");
+    }
+
+    struct DropObject(Log);
+
+    impl Object for DropObject {
+        fn name(&self) -> &'static str {
+            "DropObject"
+        }
+        fn concrete(&mut self) -> ConcreteObject<'_> {
+            ConcreteObject::Other(self)
+        }
+    }
+
+    impl Drop for DropObject {
+        fn drop(&mut self) {
+            self.0.lock().unwrap().push("drop".to_string());
+        }
+    }
+
+    #[test]
+    fn ownership() {
+        let test = Test::new();
+        let mut system = System::new();
+        let frame = system.frame(Some(Box::new(DropObject(test.log.clone()))));
+        system.get_mut(frame).unwrap().adopt(Some(Box::new(DropObject(test.log.clone()))));
+        assert_eq!(test.log(), "drop");
+        drop(system);
+        assert_eq!(test.log(), "drop drop");
+    }
+
+    #[test]
+    fn foreign_handle() {
+        let test = Test::new();
+        let TestableSystem { system: first, a, .. } = test.make_system("");
+        let TestableSystem { system: second, .. } = test.make_system("");
+        assert_eq!(first.get(a).unwrap().name(), "MockObject");
+        assert!(second.get(a).is_none());
+    }
+
+    #[test]
+    fn remove_frame() {
+        let mut test = Test::new();
+        let TestableSystem {
+            mut system,
+            a,
+            b,
+            c,
+        } = test.make_system("");
+        system.link(a, b, Relation::Then);
+        system.link(b, c, Relation::Then);
+        system.schedule(b, &mut test.task_loop).unwrap();
+        assert!(system.remove_frame(b, &mut test.task_loop).is_some());
+        assert!(system.get(b).is_none());
+        assert!(system.find_links(None, None, None).is_empty());
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), ":a");
+
+        let d = system.frame(None);
+        assert_eq!(d.index, b.index);
+        assert!(system.get(b).is_none());
+    }
+
+    #[test]
+    fn unlink() {
+        let mut test = Test::new();
+        let TestableSystem {
+            mut system,
+            a,
+            b,
+            c,
+        } = test.make_system("");
+        system.link(a, b, Relation::Then);
+        system.link(a, c, Relation::Then);
+        system.link(a, c, Relation::Arg);
+        assert_eq!(system.find_links(Some(a), None, Some(Relation::Then)).len(), 2);
+        assert!(system.unlink(a, b, Relation::Then));
+        assert!(!system.unlink(a, b, Relation::Then));
+        assert_eq!(
+            system.find_links(Some(a), None, None),
+            vec![(a, c, Relation::Then), (a, c, Relation::Arg)]
+        );
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), ":a :c");
+    }
+
+    #[test]
+    fn unresolved_links() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let start = system.frame(Some(MockObject::new("s".to_string(), &test.log)));
+        let outer = system.frame(Some(System::new()));
+        let inner = subsystem(&mut system, outer)
+            .frame(Some(MockObject::new("x".to_string(), &test.log)));
+        let empty = system.frame(None);
+        system.link(start, inner, Relation::Then);
+        system.link(inner, start, Relation::Arg);
+        assert!(system.unresolved_links().is_empty());
+
+        // Swapping the subsystem away leaves both links pointing into `outer`.
+        system.swap(outer, empty, &mut test.task_loop);
+        let path = vec!["MockObject".to_string()];
+        assert!(system.find_links(None, None, None).is_empty());
+        assert_eq!(
+            system.unresolved_links(),
+            vec![(outer, path.clone(), Relation::Then), (outer, path.clone(), Relation::Arg)]
+        );
+        assert!(!system.unlink_unresolved(empty, &path));
+        assert!(system.unlink_unresolved(outer, &path));
+        assert!(system.unresolved_links().is_empty());
+        assert!(system.validate().is_empty());
+    }
+
     // Cross-system running tests:
 
     struct CrossSystemTest {
         test: Test,
         system: Box<System>,
-        top: &'static mut Frame,
-        left: &'static mut Frame,
-        right: &'static mut Frame,
+        top: FrameId,
+        left: FrameId,
+        right: FrameId,
     }
 
     impl CrossSystemTest {
         fn new() -> Self {
             let test = Test::new();
-            let top = test.make_system("Top");
+            let mut top = test.make_system("Top");
             let TestableSystem {
                 system: left_system,
                 a: left_a,
@@ -703,8 +2317,8 @@ mod tests {
                 a: right_a,
                 ..
             } = test.make_system("Right");
-            top.b.adopt(Some(left_system));
-            top.c.adopt(Some(right_system));
+            top.system.get_mut(top.b).unwrap().adopt(Some(left_system));
+            top.system.get_mut(top.c).unwrap().adopt(Some(right_system));
             CrossSystemTest {
                 test,
                 system: top.system,
@@ -726,8 +2340,8 @@ mod tests {
         } = CrossSystemTest::new();
 
         system.link(top, left, Relation::Then);
-        top.schedule(&mut test.task_loop);
-        test.task_loop.run_iterations(2);
+        system.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_iterations(&mut system, 2).unwrap();
 
         assert_eq!(test.log(), "Top:a Left:a");
     }
@@ -743,8 +2357,8 @@ mod tests {
         } = CrossSystemTest::new();
 
         system.link(left, top, Relation::Then);
-        left.schedule(&mut test.task_loop);
-        test.task_loop.run_iterations(2);
+        system.schedule(left, &mut test.task_loop).unwrap();
+        test.task_loop.run_iterations(&mut system, 2).unwrap();
 
         assert_eq!(test.log(), "Left:a Top:a");
     }
@@ -761,12 +2375,120 @@ mod tests {
 
         system.link(left, right, Relation::Then);
         system.link(right, left, Relation::Then);
-        left.schedule(&mut test.task_loop);
-        test.task_loop.run_iterations(4);
+        system.schedule(left, &mut test.task_loop).unwrap();
+        test.task_loop.run_iterations(&mut system, 4).unwrap();
 
         assert_eq!(test.log(), "Left:a Right:a Left:a Right:a");
     }
 
+    #[test]
+    fn paths() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let top = system.frame(Some(MockObject::new("top".to_string(), &test.log)));
+
+        let left = system.frame(Some(System::new()));
+        let left_shallow = subsystem(&mut system, left).frame(Some(MockObject::new(
+            "left-shallow".to_string(),
+            &test.log,
+        )));
+        let left_inner = subsystem(&mut system, left).frame(Some(System::new()));
+        subsystem(&mut system, left_inner).frame(Some(MockObject::new(
+            "left-deep".to_string(),
+            &test.log,
+        )));
+
+        let right = system.frame(Some(System::new()));
+        let right_inner = subsystem(&mut system, right).frame(Some(System::new()));
+        let right_deep = subsystem(&mut system, right_inner).frame(Some(MockObject::new(
+            "right-deep".to_string(),
+            &test.log,
+        )));
+
+        assert_eq!(system.resolve("System/MockObject"), Some(left_shallow));
+        assert_eq!(system.resolve("System2/System/MockObject"), Some(right_deep));
+        assert_eq!(system.resolve("System2/MockObject"), None);
+        let path = system.get(right_deep).unwrap().path(&system);
+        assert_eq!(path, Some("System2/System/MockObject".to_string()));
+
+        // The link keeps pointing at the nested frame, not the shallowest
+        // one with the same name.
+        system.link(top, right_deep, Relation::Then);
+        system.swap(left, right, &mut test.task_loop);
+        system.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "top left-deep");
+    }
+
+    #[test]
+    fn rename() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let top = system.frame(Some(MockObject::new("top".to_string(), &test.log)));
+        let left = system.frame(Some(System::new()));
+        let x = subsystem(&mut system, left).frame(Some(MockObject::new(
+            "x".to_string(),
+            &test.log,
+        )));
+        let right = system.frame(Some(System::new()));
+        let y = subsystem(&mut system, right).frame(Some(MockObject::new(
+            "y".to_string(),
+            &test.log,
+        )));
+
+        assert_eq!(system.rename(top, "System"), Err(Error::NameTaken("System".to_string())));
+        assert_eq!(system.rename(top, "a/b"), Err(Error::InvalidName("a/b".to_string())));
+        assert_eq!(system.rename(x, "System"), Ok(()));
+        assert_eq!(system.resolve("System/System"), Some(x));
+
+        // After the swap the link refers to `y` by its path inside `left`.
+        system.link(top, x, Relation::Then);
+        system.rename(x, "MockObject").unwrap();
+        system.swap(left, right, &mut test.task_loop);
+        system.rename(y, "Renamed").unwrap();
+        assert_eq!(system.resolve("System/Renamed"), Some(y));
+        system.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "top y");
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let top = system.frame(Some(MockObject::new("top".to_string(), &test.log)));
+        let sub = system.frame(Some(System::new()));
+        let inner = subsystem(&mut system, sub).frame(Some(MockObject::new(
+            "inner".to_string(),
+            &test.log,
+        )));
+        let empty = system.frame(None);
+        system.link(top, inner, Relation::Then);
+        system.link(top, sub, Relation::Arg);
+        let node = |frame: FrameId| format!("frame{}", frame.generation);
+
+        let dot = system.to_dot();
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains(&format!("subgraph cluster_{} {{", node(sub))));
+        assert!(dot.contains(&format!("{} [label=\"MockObject (MockObject)\"];", node(inner))));
+        assert!(dot.contains(&format!("{} [label=\"Frame (empty)\"];", node(empty))));
+        assert!(dot.contains(&format!("{} -> {} [style=solid];", node(top), node(inner))));
+        assert!(dot.contains(&format!(
+            "{} -> {} [style=dashed, arrowhead=empty, lhead=cluster_{}];",
+            node(top),
+            node(sub),
+            node(sub)
+        )));
+
+        // Swapping the subsystem away leaves the `Then` link unresolved.
+        system.swap(sub, empty, &mut test.task_loop);
+        let dot = system.to_dot();
+        assert!(dot.contains(
+            "unresolved1 [label=\"System/MockObject\", color=red, fontcolor=red, style=dashed];"
+        ));
+        assert!(dot.contains(&format!("{} -> unresolved1 [style=solid, color=red];", node(top))));
+    }
+
     #[test]
     fn system_substitution() {
         let mut test = Test::new();
@@ -775,65 +2497,339 @@ mod tests {
         let top = system.frame(Some(MockObject::new("top".to_string(), &test.log)));
 
         let left1 = system.frame(Some(System::new()));
-        let left2 = System::from_frame(left1).unwrap().frame(
-            Some(System::new()),
-        );
-        let left3 = System::from_frame(left2).unwrap().frame(
-            Some(MockObject::new(
-                "left3".to_string(),
-                &test.log,
-            )),
-        );
+        let left2 = subsystem(&mut system, left1).frame(Some(System::new()));
+        let left3 = subsystem(&mut system, left2).frame(Some(MockObject::new(
+            "left3".to_string(),
+            &test.log,
+        )));
 
         let right1 = system.frame(Some(System::new()));
-        let right2 = System::from_frame(right1).unwrap().frame(
-            Some(System::new()),
-        );
-        let right3 = System::from_frame(right2).unwrap().frame(
-            Some(MockObject::new(
-                "right3".to_string(),
-                &test.log,
-            )),
-        );
+        let right2 = subsystem(&mut system, right1).frame(Some(System::new()));
+        let right3 = subsystem(&mut system, right2).frame(Some(MockObject::new(
+            "right3".to_string(),
+            &test.log,
+        )));
 
         system.link(top, left3, Relation::Then);
-        top.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
+        system.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
 
         assert_eq!(test.log(), "top left3");
 
-        Frame::swap(left1, right1);
-        top.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
+        system.swap(left1, right1, &mut test.task_loop);
+        system.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
 
         assert_eq!(test.log(), "top left3 top right3");
 
-        Frame::swap(left2, right2);
-        top.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
+        system.swap(left2, right2, &mut test.task_loop);
+        system.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
 
         assert_eq!(test.log(), "top left3 top right3 top left3");
 
-        Frame::swap(left3, right3);
-        top.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
+        system.swap(left3, right3, &mut test.task_loop);
+        system.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
 
         assert_eq!(test.log(), "top left3 top right3 top left3 top right3");
     }
 
-    struct SlowObject(Log);
-
-    impl SlowObject {
-        fn new(log: &Log) -> Box<Self> {
-            Box::new(SlowObject(log.clone()))
-        }
+    #[test]
+    fn remove_subsystem() {
+        let mut test = Test::new();
+        let TestableSystem {
+            mut system,
+            a: top,
+            b: plain,
+            ..
+        } = test.make_system("Top");
+        let TestableSystem { system: inner, a: inner_a, .. } = test.make_system("Inner");
+        let holder = system.frame(Some(inner));
+        system.link(top, inner_a, Relation::Then);
+        system.link(inner_a, top, Relation::Arg);
+        system.swap(holder, plain, &mut test.task_loop);
+        assert_eq!(system.links.len(), 2);
+        assert!(system.find_links(None, None, None).is_empty());
+        system.remove_frame(holder, &mut test.task_loop);
+        assert!(system.links.is_empty());
+        system.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "Top:a");
+    }
+
+    #[test]
+    fn serialize() {
+        let CrossSystemTest {
+            mut test,
+            mut system,
+            top,
+            left,
+            right,
+        } = CrossSystemTest::new();
+        system.link(top, left, Relation::Then);
+        system.link(left, right, Relation::Then);
+        let bytes = system.serialize();
+        drop(system);
+
+        let log = test.log.clone();
+        let mut registry = ObjectRegistry::new();
+        registry.register("MockObject", move || MockObject::new(String::new(), &log));
+        let mut system = registry.load(&bytes).unwrap();
+        assert_eq!(system.serialize(), bytes);
+
+        let top = resolve_element_path(&*system, &["MockObject".to_string()]).unwrap();
+        system.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "Top:a Left:a Right:a");
+    }
+
+    #[test]
+    fn deserialize_errors() {
+        let TestableSystem { system, .. } = Test::new().make_system("");
+        let bytes = system.serialize();
+        let registry = ObjectRegistry::new();
+        assert_eq!(
+            registry.load(&bytes).err(),
+            Some(DecodeError::UnknownObject("MockObject".to_string()))
+        );
+        assert_eq!(registry.load(&bytes[..6]).err(), Some(DecodeError::UnexpectedEnd));
+        assert_eq!(
+            registry.load(&[2, 0, 0, 0]).err(),
+            Some(DecodeError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn registry() {
+        let test = Test::new();
+        let mut registry = ObjectRegistry::new();
+        let log = test.log.clone();
+        registry.register("MockObject", move || MockObject::new("mock".to_string(), &log));
+        assert_eq!(registry.names(), vec!["MockObject", "Port", "System"]);
+        assert!(registry.contains("System"));
+        assert_eq!(registry.create("MockObject").unwrap().name(), "MockObject");
+        assert_eq!(
+            registry.create("Missing").err(),
+            Some(UnknownType("Missing".to_string()))
+        );
+        assert_eq!(
+            UnknownType("Missing".to_string()).to_string(),
+            "unknown object type Missing"
+        );
+    }
+
+    struct HandlerObject(Log);
+
+    impl Object for HandlerObject {
+        fn name(&self) -> &'static str {
+            "Handler"
+        }
+        fn concrete(&mut self) -> ConcreteObject<'_> {
+            ConcreteObject::Other(self)
+        }
+        fn can_run(&self) -> bool {
+            true
+        }
+        fn run(&mut self, ctx: RunContext) {
+            for error in ctx.errors() {
+                self.0.lock().unwrap().push(format!("handled({})", error));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_frame() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let empty = system.frame(None);
+        system.schedule(empty, &mut test.task_loop).unwrap();
+        assert_eq!(
+            test.task_loop.run_until_done(&mut system),
+            Err(Error::EmptyFrame(empty))
+        );
+        assert_eq!(test.task_loop.run_until_done(&mut system), Ok(()));
+    }
+
+    #[test]
+    fn element_not_found() {
+        let mut test = Test::new();
+        let TestableSystem {
+            mut system,
+            a: top,
+            b: plain,
+            c,
+        } = test.make_system("Top");
+        let TestableSystem { system: inner, a: inner_a, .. } = test.make_system("Inner");
+        let holder = system.frame(Some(inner));
+        system.link(top, inner_a, Relation::Then);
+        system.link(top, c, Relation::Then);
+        system.swap(holder, plain, &mut test.task_loop);
+        system.schedule(top, &mut test.task_loop).unwrap();
+        assert_eq!(
+            test.task_loop.run_until_done(&mut system),
+            Err(Error::ElementNotFound("MockObject".to_string()))
+        );
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "Top:a Top:c");
+    }
+
+    #[test]
+    fn error_handler() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let outer_handler = system.frame(Some(Box::new(HandlerObject(test.log.clone()))));
+        system.set_error_handler(Some(outer_handler));
+        let mut inner = System::new();
+        let empty = inner.frame(None);
+        let handler = inner.frame(Some(Box::new(HandlerObject(test.log.clone()))));
+        inner.set_error_handler(Some(handler));
+        system.frame(Some(inner));
+        let other = system.frame(None);
+
+        system.schedule(empty, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), format!("handled({})", Error::EmptyFrame(empty)));
+
+        test.log.lock().unwrap().clear();
+        system.schedule(other, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), format!("handled({})", Error::EmptyFrame(other)));
+    }
+
+    struct FailObject {
+        log: Log,
+        background: bool,
+    }
+
+    impl Object for FailObject {
+        fn name(&self) -> &'static str {
+            "Fail"
+        }
+        fn concrete(&mut self) -> ConcreteObject<'_> {
+            ConcreteObject::Other(self)
+        }
+        fn can_run(&self) -> bool {
+            true
+        }
+        fn run(&mut self, ctx: RunContext) {
+            use std::thread;
+            self.log.lock().unwrap().push("fail".to_string());
+            let error = Error::Failed("boom".to_string());
+            if self.background {
+                let background = ctx.background();
+                thread::spawn(move || background.fail(error));
+            } else {
+                ctx.fail(error);
+            }
+        }
+    }
+
+    fn make_fail_system(test: &Test, background: bool) -> (Box<System>, FrameId, FrameId, FrameId) {
+        let mut system = System::new();
+        let fail = system.frame(Some(Box::new(FailObject {
+            log: test.log.clone(),
+            background,
+        })));
+        let then = system.frame(Some(MockObject::new("then".to_string(), &test.log)));
+        let handler = system.frame(Some(Box::new(HandlerObject(test.log.clone()))));
+        system.link(fail, then, Relation::Then);
+        return (system, fail, then, handler);
+    }
+
+    #[test]
+    fn catch() {
+        for &background in [false, true].iter() {
+            let mut test = Test::new();
+            let (mut system, fail, _, handler) = make_fail_system(&test, background);
+            system.link(fail, handler, Relation::Catch);
+            system.schedule(fail, &mut test.task_loop).unwrap();
+            test.task_loop.run_until_done(&mut system).unwrap();
+            assert_eq!(test.log(), "fail handled(boom)");
+        }
+    }
+
+    #[test]
+    fn uncaught_failure() {
+        let mut test = Test::new();
+        let (mut system, fail, _, _) = make_fail_system(&test, false);
+        system.schedule(fail, &mut test.task_loop).unwrap();
+        assert_eq!(
+            test.task_loop.run_until_done(&mut system),
+            Err(Error::Failed("boom".to_string()))
+        );
+        assert_eq!(test.log(), "fail");
+    }
+
+    struct ConstObject(i32);
+
+    impl Object for ConstObject {
+        fn name(&self) -> &'static str {
+            "Const"
+        }
+        fn concrete(&mut self) -> ConcreteObject<'_> {
+            ConcreteObject::Other(self)
+        }
+        fn can_run(&self) -> bool {
+            true
+        }
+        fn run(&mut self, _: RunContext) {}
+        fn output(&self) -> Option<Value> {
+            Some(Arc::new(self.0))
+        }
+    }
+
+    struct PrintObject(Log);
+
+    impl Object for PrintObject {
+        fn name(&self) -> &'static str {
+            "Print"
+        }
+        fn concrete(&mut self) -> ConcreteObject<'_> {
+            ConcreteObject::Other(self)
+        }
+        fn can_run(&self) -> bool {
+            true
+        }
+        fn run(&mut self, ctx: RunContext) {
+            let mut names: Vec<&String> = ctx.args().keys().collect();
+            names.sort();
+            for name in names {
+                let value = ctx.arg(name).unwrap().downcast_ref::<i32>().unwrap();
+                self.0.lock().unwrap().push(format!("{}={}", name, value));
+            }
+        }
+    }
+
+    #[test]
+    fn arg() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let x = system.frame(Some(Box::new(ConstObject(1))));
+        let y = system.frame(Some(Box::new(ConstObject(2))));
+        let print = system.frame(Some(Box::new(PrintObject(test.log.clone()))));
+        system.link(x, print, Relation::Arg);
+        system.link(y, print, Relation::Arg);
+        system.link(x, print, Relation::Then);
+        system.schedule(x, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+
+        assert_eq!(test.log(), "Const=1 Const2=2");
+    }
+
+    struct SlowObject(Log);
+
+    impl SlowObject {
+        fn new(log: &Log) -> Box<Self> {
+            Box::new(SlowObject(log.clone()))
+        }
     }
 
     impl Object for SlowObject {
         fn name(&self) -> &'static str {
             "SlowObject"
         }
-        fn concrete(&mut self) -> ConcreteObject {
+        fn concrete(&mut self) -> ConcreteObject<'_> {
             ConcreteObject::Other(self)
         }
         fn can_run(&self) -> bool {
@@ -841,15 +2837,26 @@ mod tests {
         }
         fn run(&mut self, ctx: RunContext) {
             use std::{thread, time};
-            self.0.borrow_mut().push("start".to_string());
-            let mut background = ctx.background();
+            self.0.lock().unwrap().push("start".to_string());
+            let mut background = ctx.background_for(self);
             thread::spawn(move || {
                 thread::sleep(time::Duration::from_millis(10));
-                background.send_update(Box::new(()));
+                background.send_update("end");
             });
         }
-        fn update(&mut self, _: Update) {
-            self.0.borrow_mut().push("end".to_string());
+        fn update(&mut self, update: Update) {
+            self.dispatch(update);
+        }
+        fn restart(&mut self, ctx: RunContext) {
+            self.0.lock().unwrap().push("restart".to_string());
+            self.run(ctx);
+        }
+    }
+
+    impl Updatable for SlowObject {
+        type Update = &'static str;
+        fn receive(&mut self, update: &'static str) {
+            self.0.lock().unwrap().push(update.to_string());
         }
     }
 
@@ -860,9 +2867,804 @@ mod tests {
         let slow = system.frame(Some(SlowObject::new(&test.log)));
         let then = system.frame(Some(MockObject::new("mock".to_string(), &test.log)));
         system.link(slow, then, Relation::Then);
-        slow.schedule(&mut test.task_loop);
-        test.task_loop.run_until_done();
+        system.schedule(slow, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
 
         assert_eq!(test.log(), "start end mock");
     }
+
+    // Resolves once `duration` passed on a helper thread.
+    struct Delay {
+        state: Arc<Mutex<(bool, Option<Waker>)>>,
+    }
+
+    impl Delay {
+        fn new(duration: Duration) -> Delay {
+            use std::thread;
+            let state = Arc::new(Mutex::new((false, None::<Waker>)));
+            let shared = state.clone();
+            thread::spawn(move || {
+                thread::sleep(duration);
+                let mut state = shared.lock().unwrap();
+                state.0 = true;
+                if let Some(waker) = state.1.take() {
+                    waker.wake();
+                }
+            });
+            Delay { state }
+        }
+    }
+
+    impl Future for Delay {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            let mut state = self.state.lock().unwrap();
+            if state.0 {
+                return Poll::Ready(());
+            }
+            state.1 = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+    }
+
+    struct AsyncObject {
+        log: Log,
+        fail: bool,
+    }
+
+    impl Object for AsyncObject {
+        fn name(&self) -> &'static str {
+            "AsyncObject"
+        }
+        fn concrete(&mut self) -> ConcreteObject<'_> {
+            ConcreteObject::Other(self)
+        }
+        fn can_run(&self) -> bool {
+            true
+        }
+        fn run_async(&mut self, ctx: &RunContext) -> Option<RunFuture> {
+            let log = self.log.clone();
+            let updater = ctx.updater_for(self);
+            let fail = self.fail;
+            Some(Box::pin(async move {
+                log.lock().unwrap().push("start".to_string());
+                Delay::new(ms(10)).await;
+                updater.send_update("waited".to_string());
+                if fail {
+                    return Err(Error::Failed("boom".to_string()));
+                }
+                Ok(())
+            }))
+        }
+        fn update(&mut self, update: Update) {
+            self.dispatch(update);
+        }
+    }
+
+    impl Updatable for AsyncObject {
+        type Update = String;
+        fn receive(&mut self, update: String) {
+            self.log.lock().unwrap().push(update);
+        }
+    }
+
+    #[test]
+    fn run_async() {
+        for workers in 0..2 {
+            let mut test = Test::new();
+            test.task_loop.set_workers(workers);
+            let mut system = System::new();
+            let frame = system.frame(Some(Box::new(AsyncObject {
+                log: test.log.clone(),
+                fail: false,
+            })));
+            let then = system.frame(Some(MockObject::new("then".to_string(), &test.log)));
+            system.link(frame, then, Relation::Then);
+            system.schedule(frame, &mut test.task_loop).unwrap();
+            test.task_loop.run_until_done(&mut system).unwrap();
+            assert_eq!(test.log(), "start waited then");
+        }
+    }
+
+    #[test]
+    fn run_async_failure() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let frame = system.frame(Some(Box::new(AsyncObject {
+            log: test.log.clone(),
+            fail: true,
+        })));
+        let handler = system.frame(Some(Box::new(HandlerObject(test.log.clone()))));
+        system.link(frame, handler, Relation::Catch);
+        system.schedule(frame, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "start waited handled(boom)");
+    }
+
+    // Works in the background until cancelled.
+    struct CancellableObject {
+        log: Log,
+        stopped: Sender<()>,
+    }
+
+    impl Object for CancellableObject {
+        fn name(&self) -> &'static str {
+            "Cancellable"
+        }
+        fn concrete(&mut self) -> ConcreteObject<'_> {
+            ConcreteObject::Other(self)
+        }
+        fn can_run(&self) -> bool {
+            true
+        }
+        fn run(&mut self, ctx: RunContext) {
+            use std::thread;
+            self.log.lock().unwrap().push("start".to_string());
+            let background = ctx.background();
+            let log = self.log.clone();
+            let stopped = self.stopped.clone();
+            thread::spawn(move || {
+                while !background.is_cancelled() {
+                    thread::sleep(ms(1));
+                }
+                log.lock().unwrap().push("stopped".to_string());
+                stopped.send(()).unwrap();
+            });
+        }
+        fn cancel(&mut self) {
+            self.log.lock().unwrap().push("cancel".to_string());
+        }
+    }
+
+    #[test]
+    fn cancel() {
+        for mode in 0..3 {
+            let mut test = Test::new();
+            let (stopped, rx) = mpsc::channel();
+            let mut system = System::new();
+            let frame = system.frame(Some(Box::new(CancellableObject {
+                log: test.log.clone(),
+                stopped,
+            })));
+            let then = system.frame(Some(MockObject::new("then".to_string(), &test.log)));
+            system.link(frame, then, Relation::Then);
+            system.schedule(frame, &mut test.task_loop).unwrap();
+            test.task_loop.run_one(&mut system).unwrap();
+            match mode {
+                0 => assert!(test.task_loop.cancel(&mut system, frame)),
+                1 => assert!(system.remove_frame(frame, &mut test.task_loop).is_some()),
+                _ => assert!(system.swap(frame, then, &mut test.task_loop)),
+            }
+            assert!(!test.task_loop.cancel(&mut system, frame));
+            rx.recv_timeout(Duration::from_secs(1)).unwrap();
+            test.task_loop.run_until_done(&mut system).unwrap();
+            assert_eq!(test.log(), "start cancel stopped");
+        }
+    }
+
+    #[test]
+    fn trace() {
+        let test = Test::new();
+        let recorder = TraceRecorder::new();
+        let mut task_loop = TaskLoop::with_clock(Box::new(MockClock::new()));
+        task_loop.set_tracer(Some(Box::new(recorder.clone())));
+        let mut system = System::new();
+        let slow = system.frame(Some(SlowObject::new(&test.log)));
+        let then = system.frame(Some(MockObject::new("mock".to_string(), &test.log)));
+        system.link(slow, then, Relation::Then);
+        system.schedule(slow, &mut task_loop).unwrap();
+        task_loop.run_until_done(&mut system).unwrap();
+
+        let events: Vec<TraceEvent> = recorder.events().into_iter().map(|(_, event)| event).collect();
+        assert_eq!(
+            events,
+            vec![
+                TraceEvent::Schedule { frame: slow },
+                TraceEvent::RunStart { frame: slow, task: 1 },
+                TraceEvent::BackgroundStart { frame: slow, task: 1 },
+                TraceEvent::Update { frame: slow, task: 1 },
+                TraceEvent::Finish { frame: slow, task: 1, error: None },
+                TraceEvent::Traverse { from: slow, to: then, relation: Relation::Then },
+                TraceEvent::Schedule { frame: then },
+                TraceEvent::RunStart { frame: then, task: 2 },
+                TraceEvent::Finish { frame: then, task: 2, error: None },
+            ]
+        );
+        let json = recorder.to_json_lines();
+        assert_eq!(json.lines().count(), 9);
+        assert_eq!(
+            json.lines().nth(5).unwrap(),
+            format!(
+                concat!(
+                    "{{\"time\":0,\"event\":\"traverse\",",
+                    "\"from\":[{},{}],\"to\":[{},{}],\"relation\":\"Then\"}}"
+                ),
+                slow.index,
+                slow.generation,
+                then.index,
+                then.generation
+            )
+        );
+    }
+
+    #[test]
+    fn trace_links() {
+        let test = Test::new();
+        let recorder = TraceRecorder::new();
+        let mut task_loop = TaskLoop::new();
+        task_loop.set_tracer(Some(Box::new(recorder.clone())));
+        let mut system = System::new();
+        let top = system.frame(Some(MockObject::new("top".to_string(), &test.log)));
+        let left = system.frame(Some(System::new()));
+        let x = subsystem(&mut system, left).frame(Some(MockObject::new(
+            "x".to_string(),
+            &test.log,
+        )));
+        let right = system.frame(Some(System::new()));
+        let y = subsystem(&mut system, right).frame(Some(MockObject::new(
+            "y".to_string(),
+            &test.log,
+        )));
+        system.link(top, x, Relation::Then);
+        system.swap(left, right, &mut task_loop);
+
+        let path = vec!["MockObject".to_string()];
+        let events: Vec<TraceEvent> = recorder.events().into_iter().map(|(_, event)| event).collect();
+        assert_eq!(
+            events,
+            vec![
+                TraceEvent::LinkCut { holder: left, path: path.clone() },
+                TraceEvent::LinkFix { holder: left, path, frame: y },
+            ]
+        );
+    }
+
+    #[test]
+    fn chrome_trace() {
+        let test = Test::new();
+        let mut system = System::new();
+        let slow = system.frame(Some(SlowObject::new(&test.log)));
+        let mut recorder = TraceRecorder::new();
+        let error = Some(Error::Failed("boom".to_string()));
+        recorder.trace(ms(1), &TraceEvent::RunStart { frame: slow, task: 1 });
+        recorder.trace(ms(2), &TraceEvent::BackgroundStart { frame: slow, task: 1 });
+        recorder.trace(ms(5), &TraceEvent::Finish { frame: slow, task: 1, error });
+        // Still running, so left out.
+        recorder.trace(ms(6), &TraceEvent::RunStart { frame: slow, task: 2 });
+
+        let tid = slow.generation;
+        let expected = [
+            format!(
+                r#"{{"ph":"M","pid":1,"tid":{},"name":"thread_name","args":{{"name":"SlowObject"}}}}"#,
+                tid
+            ),
+            format!(
+                concat!(
+                    r#"{{"ph":"X","pid":1,"tid":{},"name":"run","cat":"task","ts":1000,"#,
+                    r#""dur":1000,"args":{{"task":1}}}}"#
+                ),
+                tid
+            ),
+            format!(
+                concat!(
+                    r#"{{"ph":"X","pid":1,"tid":{},"name":"background","cat":"task","ts":2000,"#,
+                    r#""dur":3000,"args":{{"task":1,"error":"boom"}}}}"#
+                ),
+                tid
+            ),
+        ];
+        assert_eq!(
+            recorder.to_chrome_trace(&system),
+            format!("{{\"traceEvents\":[\n{}\n]}}\n", expected.join(",\n"))
+        );
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut test = Test::new();
+        test.task_loop = TaskLoop::with_clock(Box::new(MockClock::new()));
+        let mut system = System::new();
+        let slow = system.frame(Some(SlowObject::new(&test.log)));
+        let then = system.frame(Some(MockObject::new("then".to_string(), &test.log)));
+        let other = system.frame(Some(MockObject::new("other".to_string(), &test.log)));
+        system.link(slow, then, Relation::Then);
+        system.schedule(slow, &mut test.task_loop).unwrap();
+        system.schedule(other, &mut test.task_loop).unwrap();
+        test.task_loop.schedule_after(then, Duration::from_secs(60));
+        test.task_loop.run_one(&mut system).unwrap();
+        assert_eq!(test.log(), "start");
+
+        let snapshot = test.task_loop.snapshot(&system).unwrap();
+        let bytes = snapshot.to_bytes();
+        assert_eq!(Snapshot::from_bytes(&bytes), Ok(snapshot));
+        assert_eq!(Snapshot::from_bytes(&bytes[..7]), Err(DecodeError::UnexpectedEnd));
+
+        let restored = Test::new();
+        let mut registry = ObjectRegistry::new();
+        let log = restored.log.clone();
+        registry.register("SlowObject", move || SlowObject::new(&log));
+        let log = restored.log.clone();
+        registry.register("MockObject", move || MockObject::new(String::new(), &log));
+        let clock = MockClock::new();
+        let mut task_loop = TaskLoop::with_clock(Box::new(clock.clone()));
+        let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+        let mut copy = task_loop.restore(&snapshot, &registry).unwrap();
+        task_loop.run_until_done(&mut copy).unwrap();
+        // The timer keeps the time it had left.
+        assert_eq!(clock.now(), Duration::from_secs(60));
+        assert_eq!(restored.log(), "restart start other then end then");
+
+        // The original background work still reports to its own loop.
+        test.task_loop.run_until_done(&mut system).unwrap();
+
+        // Objects away on workers can't be saved.
+        test.task_loop.set_workers(1);
+        system.schedule(other, &mut test.task_loop).unwrap();
+        test.task_loop.run_one(&mut system).unwrap();
+        assert!(test.task_loop.snapshot(&system).is_none());
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert!(test.task_loop.snapshot(&system).is_some());
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let a = system.frame(Some(MockObject::new("a".to_string(), &test.log)));
+        let b = system.frame(Some(MockObject::new("b".to_string(), &test.log)));
+        system.link(a, b, Relation::Then);
+        assert!(system.adopt(b, Some(MockObject::new("c".to_string(), &test.log))));
+        system.swap(a, b, &mut test.task_loop);
+        system.rename(a, "Renamed").unwrap();
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "c a");
+
+        for _ in 0..6 {
+            assert!(system.undo(&mut test.task_loop));
+        }
+        assert!(!system.undo(&mut test.task_loop));
+        assert!(system.elements().is_empty());
+        assert!(system.find_links(None, None, None).is_empty());
+
+        for _ in 0..6 {
+            assert!(system.redo(&mut test.task_loop));
+        }
+        assert!(!system.redo(&mut test.task_loop));
+        assert_eq!(system.resolve("Renamed"), Some(a));
+        assert_eq!(system.find_links(None, None, None), vec![(a, b, Relation::Then)]);
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "c a c a");
+
+        // Undoing the rename and the swap puts the objects back in place.
+        assert!(system.undo(&mut test.task_loop));
+        assert!(system.undo(&mut test.task_loop));
+        assert_eq!(system.resolve("MockObject"), Some(a));
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "c a c a a c");
+
+        // A new edit can't be followed by the undone ones.
+        system.link(b, a, Relation::Arg);
+        assert!(!system.redo(&mut test.task_loop));
+    }
+
+    #[test]
+    fn undo_nested_frame() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let start = system.frame(Some(MockObject::new("s".to_string(), &test.log)));
+        let outer = system.frame(Some(System::new()));
+        let y = subsystem(&mut system, outer)
+            .frame(Some(MockObject::new("y".to_string(), &test.log)));
+        let x = system.frame_in(outer, Some(MockObject::new("x".to_string(), &test.log))).unwrap();
+        assert_eq!(system.frame_in(start, None), None);
+        subsystem(&mut system, outer).set_entry(x, true);
+        subsystem(&mut system, outer).link(x, y, Relation::Then);
+        system.link(start, outer, Relation::Then);
+        system.link(start, x, Relation::Arg);
+
+        // Undoing the addition drops the links to the frame at every level.
+        assert!(system.undo(&mut test.task_loop));
+        assert!(system.undo(&mut test.task_loop));
+        assert!(system.undo(&mut test.task_loop));
+        assert!(system.get(x).is_none());
+        assert!(subsystem(&mut system, outer).find_links(None, None, None).is_empty());
+        assert!(subsystem(&mut system, outer).entries().is_empty());
+        assert!(system.validate().is_empty());
+
+        // Redoing it brings them back.
+        for _ in 0..3 {
+            assert!(system.redo(&mut test.task_loop));
+        }
+        assert_eq!(
+            subsystem(&mut system, outer).find_links(None, None, None),
+            vec![(x, y, Relation::Then)]
+        );
+        assert_eq!(subsystem(&mut system, outer).entries(), &[x]);
+        system.schedule(start, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "s x y");
+    }
+
+    #[test]
+    fn transaction() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let start = system.frame(Some(MockObject::new("s".to_string(), &test.log)));
+        let outer = system.frame(Some(System::new()));
+        let inner = subsystem(&mut system, outer)
+            .frame(Some(MockObject::new("x".to_string(), &test.log)));
+        let empty = system.frame(None);
+        system.link(start, inner, Relation::Then);
+        system.undo(&mut test.task_loop);
+
+        // Leaves the link pointing into an empty frame.
+        let result = system.transaction(&mut test.task_loop, |tx| {
+            tx.link(start, inner, Relation::Then);
+            tx.swap(outer, empty);
+            Ok(())
+        });
+        assert_eq!(result, Err(Error::ElementNotFound("System/MockObject".to_string())));
+        assert!(system.get(outer).unwrap().object().is_some());
+        assert!(system.find_links(None, None, None).is_empty());
+
+        // Undone edits can still be redone after a rollback.
+        let result = system.transaction(&mut test.task_loop, |tx| {
+            let frame = tx.frame(None);
+            tx.rename(frame, "a/b")?;
+            Ok(frame)
+        });
+        assert_eq!(result, Err(Error::InvalidName("a/b".to_string())));
+        assert_eq!(system.elements().len(), 3);
+        assert!(system.redo(&mut test.task_loop));
+        system.schedule(start, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "s x");
+
+        // Swapping back before the commit keeps the link in place.
+        let renamed = system.transaction(&mut test.task_loop, |tx| {
+            tx.swap(outer, empty);
+            tx.swap(empty, outer);
+            tx.rename(start, "Start")?;
+            Ok(tx.system().resolve("Start"))
+        });
+        assert_eq!(renamed, Ok(Some(start)));
+        assert_eq!(system.find_links(None, None, None), vec![(start, inner, Relation::Then)]);
+
+        // The whole transaction is undone at once.
+        assert!(system.undo(&mut test.task_loop));
+        assert_eq!(system.resolve("MockObject"), Some(start));
+        assert!(system.undo(&mut test.task_loop));
+        assert!(system.find_links(None, None, None).is_empty());
+
+        // Links that were broken before don't hold back later transactions.
+        system.link(start, inner, Relation::Then);
+        assert!(system.swap(outer, empty, &mut test.task_loop));
+        let other = system.frame(Some(MockObject::new("o".to_string(), &test.log)));
+        system.link(start, other, Relation::Then);
+        let result = system.transaction(&mut test.task_loop, |tx| tx.rename(start, "Start"));
+        assert_eq!(result, Ok(()));
+
+        // Relinking is rolled back and undone as a whole.
+        let result = system.transaction(&mut test.task_loop, |tx| {
+            assert!(tx.unlink(start, other, Relation::Then));
+            tx.link(start, inner, Relation::Then);
+            Err::<(), _>(Error::Failed("relink".to_string()))
+        });
+        assert_eq!(result, Err(Error::Failed("relink".to_string())));
+        assert_eq!(system.find_links(None, None, None), vec![(start, other, Relation::Then)]);
+        let result = system.transaction(&mut test.task_loop, |tx| {
+            assert!(tx.unlink(start, other, Relation::Then));
+            tx.link(start, inner, Relation::Then);
+            Ok(())
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(system.find_links(None, None, None), vec![(start, inner, Relation::Then)]);
+        assert!(system.undo(&mut test.task_loop));
+        assert_eq!(system.find_links(None, None, None), vec![(start, other, Relation::Then)]);
+    }
+
+    #[test]
+    fn validate() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let a = system.frame(Some(MockObject::new("a".to_string(), &test.log)));
+        let b = system.frame(Some(MockObject::new("b".to_string(), &test.log)));
+        let c = system.frame(Some(MockObject::new("c".to_string(), &test.log)));
+        let empty = system.frame(None);
+        let outer = system.frame(Some(System::new()));
+        let other = system.frame(None);
+        let inner = subsystem(&mut system, outer)
+            .frame(Some(MockObject::new("x".to_string(), &test.log)));
+        let twin = subsystem(&mut system, outer)
+            .frame(Some(MockObject::new("y".to_string(), &test.log)));
+        system.get_mut(twin).unwrap().name = "MockObject".to_string();
+        let duplicate = Diagnostic::DuplicateName("System/MockObject".to_string());
+        assert_eq!(system.validate(), vec![duplicate]);
+
+        // The cycle of `a` and `b` can be left through `Catch`.
+        system.link(a, b, Relation::Then);
+        system.link(b, a, Relation::Then);
+        system.link(b, empty, Relation::Catch);
+        system.link(c, c, Relation::Then);
+        system.link(a, inner, Relation::Then);
+        system.link(a, outer, Relation::Then);
+        system.swap(outer, other, &mut test.task_loop);
+        system.link(a, other, Relation::Then);
+        let diagnostics = system.validate();
+        assert_eq!(diagnostics, vec![
+            Diagnostic::UnresolvedLink("System/MockObject".to_string()),
+            Diagnostic::DuplicateName("Frame2/MockObject".to_string()),
+            Diagnostic::EmptyTarget("Frame".to_string()),
+            Diagnostic::EmptyTarget("System".to_string()),
+            Diagnostic::NotRunnable("Frame2".to_string()),
+            Diagnostic::ClosedCycle(vec!["MockObject3".to_string()]),
+        ]);
+        assert_eq!(diagnostics[0].to_string(), "link to System/MockObject can't be resolved");
+    }
+
+    #[test]
+    fn entries() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let top = system.frame(Some(MockObject::new("top".to_string(), &test.log)));
+        let outer = system.frame(Some(System::new()));
+        let inner = subsystem(&mut system, outer);
+        let first = inner.frame(Some(MockObject::new("first".to_string(), &test.log)));
+        let second = inner.frame(Some(MockObject::new("second".to_string(), &test.log)));
+        inner.link(first, second, Relation::Then);
+        system.link(top, outer, Relation::Then);
+        system.schedule(top, &mut test.task_loop).unwrap();
+        assert_eq!(
+            test.task_loop.run_until_done(&mut system),
+            Err(Error::CannotRun(outer))
+        );
+        assert_eq!(test.log(), "top");
+
+        subsystem(&mut system, outer).set_entry(first, true);
+        assert_eq!(subsystem(&mut system, outer).entries(), &[first]);
+        assert!(system.validate().is_empty());
+        system.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "top top first second");
+
+        let log = test.log.clone();
+        let mut registry = ObjectRegistry::new();
+        registry.register("MockObject", move || MockObject::new("loaded".to_string(), &log));
+        let mut loaded = registry.load(&system.serialize()).unwrap();
+        let outer = loaded.resolve("System").unwrap();
+        let first = loaded.resolve("System/MockObject").unwrap();
+        assert_eq!(subsystem(&mut loaded, outer).entries(), &[first]);
+
+        // Objects that can't run aren't scheduled. The error goes to the
+        // error handler if there is one.
+        let frame = system.frame(Some(Box::new(DropObject(test.log.clone()))));
+        assert_eq!(
+            system.schedule(frame, &mut test.task_loop),
+            Err(Error::CannotRun(frame))
+        );
+        assert!(!test.task_loop.run_one(&mut system).unwrap());
+        let handler = system.frame(Some(MockObject::new("handler".to_string(), &test.log)));
+        system.set_error_handler(Some(handler));
+        system.schedule(frame, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "top top first second handler");
+    }
+
+    #[test]
+    fn ports() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let top = system.frame(Some(MockObject::new("top".to_string(), &test.log)));
+        let outer = system.frame(Some(System::new()));
+        let bottom = system.frame(Some(MockObject::new("bottom".to_string(), &test.log)));
+        let inner = subsystem(&mut system, outer);
+        let input = inner.add_input("in").unwrap();
+        let output = inner.add_output("out").unwrap();
+        assert_eq!(inner.add_input("in"), Err(Error::NameTaken("in".to_string())));
+        let work = inner.frame(Some(MockObject::new("work".to_string(), &test.log)));
+        inner.link(input, work, Relation::Then);
+        inner.link(work, output, Relation::Then);
+        system.link(top, outer, Relation::Then);
+        system.link(outer, bottom, Relation::Then);
+        system.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "top work bottom");
+
+        let log = test.log.clone();
+        let mut registry = ObjectRegistry::new();
+        registry.register("MockObject", move || MockObject::new(String::new(), &log));
+        let mut loaded = registry.load(&system.serialize()).unwrap();
+        let top = loaded.resolve("MockObject").unwrap();
+        let output = loaded.resolve("System/out").unwrap();
+        assert_eq!(loaded.get(output).unwrap().object().unwrap().name(), "Port");
+        loaded.schedule(top, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut loaded).unwrap();
+        assert_eq!(test.log(), "top work bottom top work bottom");
+    }
+
+    #[test]
+    fn instantiate() {
+        let mut test = Test::new();
+        let mut template = System::new();
+        let input = template.add_input("in").unwrap();
+        let output = template.add_output("out").unwrap();
+        let work = template.frame(Some(MockObject::new("work".to_string(), &test.log)));
+        template.link(input, work, Relation::Then);
+        template.link(work, output, Relation::Then);
+        assert_eq!(
+            template.instantiate(&ObjectRegistry::new()).err(),
+            Some(UnknownType("MockObject".to_string()))
+        );
+
+        let log = test.log.clone();
+        let mut registry = ObjectRegistry::new();
+        registry.register("MockObject", move || MockObject::new(String::new(), &log));
+        let mut system = System::new();
+        let first = system.frame(Some(template.instantiate(&registry).unwrap()));
+        let second = system.frame(Some(template.instantiate(&registry).unwrap()));
+        system.link(first, second, Relation::Then);
+        let copy = system.resolve("System2/MockObject").unwrap();
+        assert_ne!(copy, work);
+        let input = system.resolve("System2/in").unwrap();
+        assert_eq!(
+            subsystem(&mut system, second).find_links(None, Some(copy), None),
+            vec![(input, copy, Relation::Then)]
+        );
+        system.schedule(first, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), "work work");
+    }
+
+    #[test]
+    fn cached_paths() {
+        let mut test = Test::new();
+        let mut system = System::new();
+        let first = system.frame(Some(System::new()));
+        let second = system.frame(Some(System::new()));
+        let inner = subsystem(&mut system, first)
+            .frame(Some(MockObject::new("inner".to_string(), &test.log)));
+        assert_eq!(system.path(inner), Some(vec![first, inner]));
+
+        // Paths found before are checked against the current tree.
+        system.swap(first, second, &mut test.task_loop);
+        assert_eq!(system.path(inner), Some(vec![second, inner]));
+        assert_eq!(system.get(inner).unwrap().name(), "MockObject");
+        subsystem(&mut system, second).remove_frame(inner, &mut test.task_loop);
+        assert!(system.get(inner).is_none());
+        assert!(system.paths.borrow().is_empty());
+    }
+
+    #[test]
+    fn away_on_worker() {
+        let mut test = Test::new();
+        test.task_loop.set_workers(1);
+        let TestableSystem { mut system, a, b, .. } = test.make_system("");
+        system.schedule(a, &mut test.task_loop).unwrap();
+        test.task_loop.run_one(&mut system).unwrap();
+        assert!(!system.swap(a, b, &mut test.task_loop));
+        assert!(!system.adopt(a, None));
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), ":a");
+        assert!(system.swap(a, b, &mut test.task_loop));
+        assert!(system.undo(&mut test.task_loop));
+        assert_eq!(system.get(a).unwrap().object().unwrap().name(), "MockObject");
+
+        // Frames can't be taken out while their objects, or objects inside
+        // them, are away.
+        let mut system = System::new();
+        let holder = system.frame(Some(System::new()));
+        let inner = system.frame_in(holder, Some(MockObject::new("x".to_string(), &test.log)));
+        let frame = system.frame(Some(MockObject::new("y".to_string(), &test.log)));
+        system.schedule(frame, &mut test.task_loop).unwrap();
+        test.task_loop.run_one(&mut system).unwrap();
+        assert!(!system.undo(&mut test.task_loop));
+        assert!(system.remove_frame(frame, &mut test.task_loop).is_none());
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert!(system.undo(&mut test.task_loop));
+        assert!(system.redo(&mut test.task_loop));
+        assert!(system.get(frame).unwrap().object().is_some());
+
+        system.schedule(inner.unwrap(), &mut test.task_loop).unwrap();
+        test.task_loop.run_one(&mut system).unwrap();
+        assert!(!system.adopt(holder, None));
+        assert!(!system.swap(holder, frame, &mut test.task_loop));
+        assert!(system.remove_frame(holder, &mut test.task_loop).is_none());
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.log(), ":a y x");
+        assert!(system.remove_frame(holder, &mut test.task_loop).is_some());
+    }
+
+    #[test]
+    fn undo_unlink() {
+        let mut test = Test::new();
+        let TestableSystem {
+            mut system,
+            a,
+            b,
+            c,
+        } = test.make_system("");
+        system.link(a, b, Relation::Then);
+        system.link(a, c, Relation::Then);
+        assert!(system.unlink(a, b, Relation::Then));
+        assert!(system.undo(&mut test.task_loop));
+        assert_eq!(
+            system.find_links(None, None, None),
+            vec![(a, b, Relation::Then), (a, c, Relation::Then)]
+        );
+        assert!(system.redo(&mut test.task_loop));
+        assert_eq!(system.find_links(None, None, None), vec![(a, c, Relation::Then)]);
+
+        // Undoing the unlink first lets the links be undone too.
+        for _ in 0..3 {
+            assert!(system.undo(&mut test.task_loop));
+        }
+        assert!(system.find_links(None, None, None).is_empty());
+    }
+
+    // Leaves its `Updater` behind. Its future resolves right away if the
+    // flag is set, and never otherwise.
+    struct LingeringObject(Arc<Mutex<Option<Updater<()>>>>, bool);
+
+    impl Object for LingeringObject {
+        fn name(&self) -> &'static str {
+            "LingeringObject"
+        }
+        fn concrete(&mut self) -> ConcreteObject<'_> {
+            ConcreteObject::Other(self)
+        }
+        fn can_run(&self) -> bool {
+            true
+        }
+        fn run_async(&mut self, ctx: &RunContext) -> Option<RunFuture> {
+            *self.0.lock().unwrap() = Some(ctx.updater_for(self));
+            if self.1 {
+                return Some(Box::pin(async { Ok(()) }));
+            }
+            Some(Box::pin(std::future::pending()))
+        }
+        fn update(&mut self, update: Update) {
+            self.dispatch(update);
+        }
+    }
+
+    impl Updatable for LingeringObject {
+        type Update = ();
+        fn receive(&mut self, _: ()) {}
+    }
+
+    #[test]
+    fn cancel_future() {
+        let mut test = Test::new();
+        let updater = Arc::new(Mutex::new(None));
+        let mut system = System::new();
+        let frame = system.frame(Some(Box::new(LingeringObject(updater.clone(), false))));
+        system.schedule(frame, &mut test.task_loop).unwrap();
+        test.task_loop.run_one(&mut system).unwrap();
+        assert!(test.task_loop.cancel(&mut system, frame));
+        assert!(test.task_loop.cancelled.is_empty());
+        updater.lock().unwrap().as_ref().unwrap().send_update(());
+        test.task_loop.run_until_done(&mut system).unwrap();
+    }
+
+    #[test]
+    fn late_update() {
+        let mut test = Test::new();
+        let updater = Arc::new(Mutex::new(None));
+        let mut system = System::new();
+        let frame = system.frame(Some(Box::new(LingeringObject(updater.clone(), true))));
+        system.schedule(frame, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        updater.lock().unwrap().as_ref().unwrap().send_update(());
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.task_loop.retired.len(), 1);
+
+        // Ids are forgotten once their `Updater`s are gone.
+        updater.lock().unwrap().take();
+        system.schedule(frame, &mut test.task_loop).unwrap();
+        test.task_loop.run_until_done(&mut system).unwrap();
+        assert_eq!(test.task_loop.retired.len(), 1);
+    }
 }
@@ -0,0 +1,76 @@
+use crate::history::Edit;
+use crate::{ConcreteObject, Error, FrameId, Object, RunContext, System, TaskLoop};
+
+// Object of the frames added by `System::add_input` and `System::add_output`.
+// Running it does nothing, so runs pass straight through to its links.
+pub struct Port;
+
+impl Object for Port {
+    fn name(&self) -> &'static str {
+        "Port"
+    }
+    fn can_run(&self) -> bool {
+        true
+    }
+    fn run(&mut self, _: RunContext) {}
+    fn clone_object(&self) -> Option<Box<dyn Object>> {
+        Some(Box::new(Port))
+    }
+    fn concrete(&mut self) -> ConcreteObject<'_> {
+        ConcreteObject::Other(self)
+    }
+}
+
+impl System {
+    // Adds a port that runs when a frame holding this system runs, as an
+    // entry frame. Link it to the frames the system should start with.
+    pub fn add_input(&mut self, name: &str) -> Result<FrameId, Error> {
+        let port = self.add_port(name)?;
+        self.set_entry(port, true);
+        return Ok(port);
+    }
+    // Adds a port that finishes a frame holding this system once it runs,
+    // following the links of that frame in the enclosing system.
+    pub fn add_output(&mut self, name: &str) -> Result<FrameId, Error> {
+        let port = self.add_port(name)?;
+        self.set_output(port, true);
+        return Ok(port);
+    }
+    // Marks a frame of this system as an output port.
+    pub fn set_output(&mut self, frame: FrameId, output: bool) {
+        self.outputs.retain(|&other| other != frame);
+        if output {
+            self.outputs.push(frame);
+        }
+    }
+    pub fn outputs(&self) -> &[FrameId] {
+        &self.outputs
+    }
+    fn add_port(&mut self, name: &str) -> Result<FrameId, Error> {
+        if name.is_empty() || name.contains('/') {
+            return Err(Error::InvalidName(name.to_string()));
+        }
+        if self.has_name(name) {
+            return Err(Error::NameTaken(name.to_string()));
+        }
+        let port = self.insert(name.to_string(), Some(Box::new(Port)));
+        self.record(Edit::Frame(port, None));
+        return Ok(port);
+    }
+    // Finishes the frame holding the system of `frame` if `frame` is one of
+    // its output ports.
+    pub(crate) fn leave(&mut self, frame: FrameId, task_loop: &mut TaskLoop) -> Result<(), Error> {
+        let path = match self.path(frame) {
+            Some(path) if path.len() >= 2 => path,
+            _ => return Ok(()),
+        };
+        let holder = path[path.len() - 2];
+        let output = self
+            .subsystem_mut(&path[..path.len() - 1])
+            .is_some_and(|system| system.outputs.contains(&frame));
+        if !output {
+            return Ok(());
+        }
+        return self.run_finished(holder, task_loop);
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use crate::{DecodeError, Object, Port, System};
+
+// Constructs objects from the names reported by `Object::name`.
+pub struct ObjectRegistry {
+    constructors: HashMap<String, Box<dyn Fn() -> Box<dyn Object>>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct UnknownType(pub String);
+
+impl fmt::Display for UnknownType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown object type {}", self.0)
+    }
+}
+
+impl error::Error for UnknownType {}
+
+impl From<UnknownType> for DecodeError {
+    fn from(unknown: UnknownType) -> DecodeError {
+        DecodeError::UnknownObject(unknown.0)
+    }
+}
+
+impl ObjectRegistry {
+    // Registry that already knows how to construct a `System` and a `Port`.
+    pub fn new() -> ObjectRegistry {
+        let mut registry = ObjectRegistry { constructors: HashMap::new() };
+        registry.register("System", || System::new());
+        registry.register("Port", || Box::new(Port));
+        return registry;
+    }
+    // `name` should match the `Object::name` of the constructed objects.
+    // Registering a name again replaces the previous constructor.
+    pub fn register<F>(&mut self, name: &str, constructor: F)
+    where
+        F: Fn() -> Box<dyn Object> + 'static,
+    {
+        self.constructors.insert(name.to_string(), Box::new(constructor));
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.constructors.contains_key(name)
+    }
+    // Registered names, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.constructors.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        return names;
+    }
+    pub fn create(&self, name: &str) -> Result<Box<dyn Object>, UnknownType> {
+        match self.constructors.get(name) {
+            Some(constructor) => Ok(constructor()),
+            None => Err(UnknownType(name.to_string())),
+        }
+    }
+    // Restores a system saved with `Object::serialize`.
+    pub fn load(&self, bytes: &[u8]) -> Result<Box<System>, DecodeError> {
+        System::load(bytes, self)
+    }
+}
+
+impl Default for ObjectRegistry {
+    fn default() -> ObjectRegistry {
+        ObjectRegistry::new()
+    }
+}
@@ -0,0 +1,191 @@
+use std::time::Duration;
+
+use crate::encoding::{self, Reader};
+use crate::{
+    resolve_element_path, CancelToken, DecodeError, Frame, FrameId, Object, ObjectRegistry, System,
+    Task, TaskLoop,
+};
+
+// Version of the binary format produced by `Snapshot::to_bytes`.
+const SNAPSHOT_VERSION: u32 = 1;
+
+// Checkpoint of a live run: the serialized graph, the queue of frames to run,
+// the frames that were running, pending `After` joins and timers. Frames are
+// referred to by their paths. Errors waiting for error handlers aren't kept.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    graph: Vec<u8>,
+    // Frames that were running come first and are marked for a restart.
+    queue: Vec<(Vec<String>, bool)>,
+    arrived: Vec<(Vec<String>, Vec<Vec<String>>)>,
+    // Time left until each timer fires, and its period.
+    timers: Vec<(Vec<String>, Duration, Option<Duration>)>,
+}
+
+impl TaskLoop {
+    // Take snapshots between calls to `run_one`. Returns None while a worker
+    // is busy, as objects away on workers can't be saved.
+    pub fn snapshot(&self, system: &System) -> Option<Snapshot> {
+        if !self.in_flight.is_empty() {
+            return None;
+        }
+        let path = |frame: FrameId| system.element_path(frame);
+        let mut running: Vec<(u64, FrameId)> =
+            self.background.iter().map(|(&id, task)| (id, task.frame)).collect();
+        running.sort_by_key(|&(id, _)| id);
+        let mut queue: Vec<(Vec<String>, bool)> = Vec::new();
+        for (_, frame) in running {
+            if let Some(path) = path(frame) {
+                queue.push((path, true));
+            }
+        }
+        for task in self.tasks.iter() {
+            if let Some(path) = path(task.frame) {
+                queue.push((path, task.restart));
+            }
+        }
+        let mut frames = Vec::new();
+        collect_frames(system, &mut frames);
+        let arrived = frames
+            .iter()
+            .filter(|frame| !frame.arrived.is_empty())
+            .filter_map(|frame| {
+                let arrived = frame.arrived.iter().filter_map(|&frame| path(frame)).collect();
+                Some((path(frame.id)?, arrived))
+            })
+            .collect();
+        let now = self.now();
+        let timers = self
+            .timers
+            .iter()
+            .filter_map(|(&(deadline, _), timer)| {
+                Some((path(timer.frame)?, deadline.saturating_sub(now), timer.period))
+            })
+            .collect();
+        return Some(Snapshot {
+            graph: system.serialize(),
+            queue,
+            arrived,
+            timers,
+        });
+    }
+    // Loads the graph of `snapshot` and picks its run up where it was. Frames
+    // that were running start again through `Object::restart`. Meant for a
+    // loop with nothing to do yet.
+    pub fn restore(
+        &mut self,
+        snapshot: &Snapshot,
+        registry: &ObjectRegistry,
+    ) -> Result<Box<System>, DecodeError> {
+        let mut system = registry.load(&snapshot.graph)?;
+        for &(ref path, restart) in snapshot.queue.iter() {
+            let frame = resolve(&system, path)?;
+            if !restart {
+                system.get_mut(frame).unwrap().scheduled = true;
+            }
+            self.post(Task {
+                frame,
+                token: CancelToken::default(),
+                restart,
+            });
+        }
+        for (path, arrived) in snapshot.arrived.iter() {
+            let frame = resolve(&system, path)?;
+            let arrived = arrived
+                .iter()
+                .map(|path| resolve(&system, path))
+                .collect::<Result<_, _>>()?;
+            system.get_mut(frame).unwrap().arrived = arrived;
+        }
+        for &(ref path, remaining, period) in snapshot.timers.iter() {
+            let frame = resolve(&system, path)?;
+            let deadline = self.now() + remaining;
+            self.add_timer(deadline, frame, period);
+        }
+        return Ok(system);
+    }
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encoding::write_u32(&mut out, SNAPSHOT_VERSION);
+        encoding::write_bytes(&mut out, &self.graph);
+        encoding::write_u32(&mut out, self.queue.len() as u32);
+        for &(ref path, restart) in self.queue.iter() {
+            encoding::write_names(&mut out, path);
+            encoding::write_u8(&mut out, restart as u8);
+        }
+        encoding::write_u32(&mut out, self.arrived.len() as u32);
+        for (path, arrived) in self.arrived.iter() {
+            encoding::write_names(&mut out, path);
+            encoding::write_u32(&mut out, arrived.len() as u32);
+            for path in arrived.iter() {
+                encoding::write_names(&mut out, path);
+            }
+        }
+        encoding::write_u32(&mut out, self.timers.len() as u32);
+        for &(ref path, remaining, period) in self.timers.iter() {
+            encoding::write_names(&mut out, path);
+            encoding::write_u64(&mut out, remaining.as_micros() as u64);
+            match period {
+                Some(period) => {
+                    encoding::write_u8(&mut out, 1);
+                    encoding::write_u64(&mut out, period.as_micros() as u64);
+                }
+                None => encoding::write_u8(&mut out, 0),
+            }
+        }
+        return out;
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let version = reader.read_u32()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let graph = reader.read_bytes()?;
+        let mut queue = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            queue.push((reader.read_names()?, reader.read_u8()? != 0));
+        }
+        let mut arrived = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            let path = reader.read_names()?;
+            let mut paths = Vec::new();
+            for _ in 0..reader.read_u32()? {
+                paths.push(reader.read_names()?);
+            }
+            arrived.push((path, paths));
+        }
+        let mut timers = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            let path = reader.read_names()?;
+            let remaining = Duration::from_micros(reader.read_u64()?);
+            let period = match reader.read_u8()? {
+                0 => None,
+                _ => Some(Duration::from_micros(reader.read_u64()?)),
+            };
+            timers.push((path, remaining, period));
+        }
+        return Ok(Snapshot {
+            graph,
+            queue,
+            arrived,
+            timers,
+        });
+    }
+}
+
+fn resolve(system: &System, path: &[String]) -> Result<FrameId, DecodeError> {
+    resolve_element_path(system, path).ok_or_else(|| DecodeError::UnresolvedPath(path.to_vec()))
+}
+
+fn collect_frames<'a>(object: &'a dyn Object, frames: &mut Vec<&'a Frame>) {
+    for frame in object.elements() {
+        frames.push(frame);
+        if let Some(ref object) = frame.object {
+            collect_frames(&**object, frames);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use crate::{FrameId, Link, LinkEnd, Object, ObjectRegistry, System, UnknownType};
+
+impl System {
+    // Deep copy of this system and its subsystems to be adopted by another
+    // frame, so that a system can serve as a template. Links between the
+    // copied frames lead to their copies. Objects are copied through
+    // `Object::clone_object`, or else constructed by `registry` from their
+    // serialized state. Runs, pending errors and edit history aren't copied.
+    pub fn instantiate(&self, registry: &ObjectRegistry) -> Result<Box<System>, UnknownType> {
+        self.copy(registry, &mut HashMap::new())
+    }
+    // `copies` maps the frames copied so far to their copies.
+    fn copy(
+        &self,
+        registry: &ObjectRegistry,
+        copies: &mut HashMap<FrameId, FrameId>,
+    ) -> Result<Box<System>, UnknownType> {
+        let mut copy = System::new();
+        for frame in self.frames.iter().flatten() {
+            let object = match frame.object {
+                Some(ref object) => Some(copy_object(&**object, registry, copies)?),
+                None => None,
+            };
+            let id = copy.insert(frame.name.clone(), object);
+            copies.insert(frame.id, id);
+        }
+        let copy_end = |link_end: &LinkEnd| match *link_end {
+            LinkEnd::Frame(frame) => copies.get(&frame).map(|&frame| LinkEnd::Frame(frame)),
+            LinkEnd::FrameElement(frame, ref path) => {
+                copies.get(&frame).map(|&frame| LinkEnd::FrameElement(frame, path.clone()))
+            }
+        };
+        // Links with ends that no longer exist are dropped.
+        for link in self.links.iter() {
+            if let (Some(a), Some(b)) = (copy_end(&link.a), copy_end(&link.b)) {
+                copy.links.push(Link {
+                    relation: link.relation,
+                    a,
+                    b,
+                });
+            }
+        }
+        copy.error_handler = self.error_handler.and_then(|frame| copies.get(&frame).copied());
+        copy.entries = self.entries.iter().filter_map(|frame| copies.get(frame).copied()).collect();
+        copy.outputs = self.outputs.iter().filter_map(|frame| copies.get(frame).copied()).collect();
+        return Ok(copy);
+    }
+}
+
+fn copy_object(
+    object: &dyn Object,
+    registry: &ObjectRegistry,
+    copies: &mut HashMap<FrameId, FrameId>,
+) -> Result<Box<dyn Object>, UnknownType> {
+    if let Some(system) = object.system() {
+        return Ok(system.copy(registry, copies)?);
+    }
+    if let Some(copy) = object.clone_object() {
+        return Ok(copy);
+    }
+    let mut copy = registry.create(object.name())?;
+    copy.deserialize(object.serialize());
+    return Ok(copy);
+}
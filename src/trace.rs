@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{Error, FrameId, Relation, System};
+
+// Receives what a `TaskLoop` does, stamped with the time of its clock.
+pub trait Tracer {
+    fn trace(&mut self, time: Duration, event: &TraceEvent);
+}
+
+// Runs are identified by the id the loop allocated when starting them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceEvent {
+    Schedule { frame: FrameId },
+    RunStart { frame: FrameId, task: u64 },
+    BackgroundStart { frame: FrameId, task: u64 },
+    Update { frame: FrameId, task: u64 },
+    Finish { frame: FrameId, task: u64, error: Option<Error> },
+    Cancel { frame: FrameId, task: u64 },
+    // A `Then`, `After` or `Catch` link was followed.
+    Traverse { from: FrameId, to: FrameId, relation: Relation },
+    // A link end pointing into the object held by `holder` became a path.
+    LinkCut { holder: FrameId, path: Vec<String> },
+    // A path into the object held by `holder` resolved to `frame` again.
+    LinkFix { holder: FrameId, path: Vec<String>, frame: FrameId },
+}
+
+// Keeps every event it receives. Clones share the same trace, so one can be
+// handed to the loop and the other inspected afterwards.
+#[derive(Clone, Default)]
+pub struct TraceRecorder {
+    events: Arc<Mutex<Vec<(Duration, TraceEvent)>>>,
+}
+
+// Part of a run between two trace events.
+struct Span {
+    frame: FrameId,
+    name: &'static str,
+    task: u64,
+    start: Duration,
+    end: Duration,
+    error: Option<String>,
+}
+
+impl TraceRecorder {
+    pub fn new() -> TraceRecorder {
+        TraceRecorder::default()
+    }
+    pub fn events(&self) -> Vec<(Duration, TraceEvent)> {
+        self.events.lock().unwrap().clone()
+    }
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+    // One JSON object per event, with the time in microseconds.
+    pub fn to_json_lines(&self) -> String {
+        let mut out = String::new();
+        for (time, event) in self.events.lock().unwrap().iter() {
+            out.push_str(&event_json(*time, event));
+            out.push('\n');
+        }
+        return out;
+    }
+    pub fn write_json_lines(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        out.write_all(self.to_json_lines().as_bytes())
+    }
+    // Chrome Trace Event JSON for timeline viewers like Perfetto. Every frame
+    // gets a track named by its path in `system`, showing the synchronous
+    // part of each run followed by its background work. Spans that haven't
+    // ended yet are left out.
+    pub fn to_chrome_trace(&self, system: &System) -> String {
+        let mut open: HashMap<u64, (FrameId, &'static str, Duration)> = HashMap::new();
+        let mut frames = Vec::new();
+        let mut spans = Vec::new();
+        for (time, event) in self.events.lock().unwrap().iter() {
+            let (task, error, background) = match *event {
+                TraceEvent::RunStart { frame, task } => {
+                    if !frames.contains(&frame) {
+                        frames.push(frame);
+                    }
+                    open.insert(task, (frame, "run", *time));
+                    continue;
+                }
+                TraceEvent::BackgroundStart { task, .. } => (task, None, true),
+                TraceEvent::Finish { task, ref error, .. } => {
+                    (task, error.as_ref().map(|error| error.to_string()), false)
+                }
+                TraceEvent::Cancel { task, .. } => (task, Some("cancelled".to_string()), false),
+                _ => continue,
+            };
+            if let Some((frame, name, start)) = open.remove(&task) {
+                spans.push(Span {
+                    frame,
+                    name,
+                    task,
+                    start,
+                    end: *time,
+                    error,
+                });
+                if background {
+                    open.insert(task, (frame, "background", *time));
+                }
+            }
+        }
+        let mut events = Vec::new();
+        for frame in frames {
+            let name = match system.element_path(frame) {
+                Some(path) => path.join("/"),
+                None => frame_json(frame),
+            };
+            events.push(format!(
+                concat!(
+                    "{{\"ph\":\"M\",\"pid\":1,\"tid\":{},",
+                    "\"name\":\"thread_name\",\"args\":{{\"name\":{}}}}}"
+                ),
+                frame.generation,
+                string_json(&name)
+            ));
+        }
+        for span in spans {
+            let mut args = format!("\"task\":{}", span.task);
+            if let Some(error) = span.error {
+                let _ = write!(args, ",\"error\":{}", string_json(&error));
+            }
+            events.push(format!(
+                concat!(
+                    "{{\"ph\":\"X\",\"pid\":1,\"tid\":{},\"name\":\"{}\",",
+                    "\"cat\":\"task\",\"ts\":{},\"dur\":{},\"args\":{{{}}}}}"
+                ),
+                span.frame.generation,
+                span.name,
+                span.start.as_micros(),
+                (span.end - span.start).as_micros(),
+                args
+            ));
+        }
+        return format!("{{\"traceEvents\":[\n{}\n]}}\n", events.join(",\n"));
+    }
+}
+
+impl Tracer for TraceRecorder {
+    fn trace(&mut self, time: Duration, event: &TraceEvent) {
+        self.events.lock().unwrap().push((time, event.clone()));
+    }
+}
+
+impl TraceEvent {
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            TraceEvent::Schedule { .. } => "schedule",
+            TraceEvent::RunStart { .. } => "run_start",
+            TraceEvent::BackgroundStart { .. } => "background_start",
+            TraceEvent::Update { .. } => "update",
+            TraceEvent::Finish { .. } => "finish",
+            TraceEvent::Cancel { .. } => "cancel",
+            TraceEvent::Traverse { .. } => "traverse",
+            TraceEvent::LinkCut { .. } => "link_cut",
+            TraceEvent::LinkFix { .. } => "link_fix",
+        }
+    }
+}
+
+fn event_json(time: Duration, event: &TraceEvent) -> String {
+    let mut out = format!("{{\"time\":{},\"event\":\"{}\"", time.as_micros(), event.kind());
+    match *event {
+        TraceEvent::Schedule { frame } => {
+            let _ = write!(out, ",\"frame\":{}", frame_json(frame));
+        }
+        TraceEvent::RunStart { frame, task } |
+        TraceEvent::BackgroundStart { frame, task } |
+        TraceEvent::Update { frame, task } |
+        TraceEvent::Cancel { frame, task } => {
+            let _ = write!(out, ",\"frame\":{},\"task\":{}", frame_json(frame), task);
+        }
+        TraceEvent::Finish { frame, task, ref error } => {
+            let _ = write!(out, ",\"frame\":{},\"task\":{}", frame_json(frame), task);
+            if let Some(ref error) = *error {
+                let _ = write!(out, ",\"error\":{}", string_json(&error.to_string()));
+            }
+        }
+        TraceEvent::Traverse { from, to, relation } => {
+            let _ = write!(
+                out,
+                ",\"from\":{},\"to\":{},\"relation\":\"{:?}\"",
+                frame_json(from),
+                frame_json(to),
+                relation
+            );
+        }
+        TraceEvent::LinkCut { holder, ref path } => {
+            let _ = write!(
+                out,
+                ",\"holder\":{},\"path\":{}",
+                frame_json(holder),
+                string_json(&path.join("/"))
+            );
+        }
+        TraceEvent::LinkFix { holder, ref path, frame } => {
+            let _ = write!(
+                out,
+                ",\"holder\":{},\"path\":{},\"frame\":{}",
+                frame_json(holder),
+                string_json(&path.join("/")),
+                frame_json(frame)
+            );
+        }
+    }
+    out.push('}');
+    return out;
+}
+
+pub fn frame_json(frame: FrameId) -> String {
+    format!("[{},{}]", frame.index, frame.generation)
+}
+
+pub fn string_json(string: &str) -> String {
+    let mut out = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    return out;
+}
@@ -0,0 +1,89 @@
+use std::mem;
+
+use crate::history::Edit;
+use crate::{Error, FrameId, Object, Relation, System, TaskLoop};
+
+// Edits made inside `System::transaction`. Only edits that can be undone are
+// offered, so that all of them can be rolled back.
+pub struct Transaction<'a> {
+    system: &'a mut System,
+    task_loop: &'a mut TaskLoop,
+    // Frames whose objects were swapped. Links into them are fixed at commit,
+    // so that a later swap can't leave them pointing into the wrong object.
+    swapped: Vec<FrameId>,
+}
+
+impl<'a> Transaction<'a> {
+    // The system as edited so far.
+    pub fn system(&self) -> &System {
+        self.system
+    }
+    pub fn frame(&mut self, object: Option<Box<dyn Object>>) -> FrameId {
+        self.system.frame(object)
+    }
+    pub fn frame_in(&mut self, holder: FrameId, object: Option<Box<dyn Object>>) -> Option<FrameId> {
+        self.system.frame_in(holder, object)
+    }
+    pub fn link(&mut self, a: FrameId, b: FrameId, relation: Relation) {
+        self.system.link(a, b, relation);
+    }
+    pub fn unlink(&mut self, a: FrameId, b: FrameId, relation: Relation) -> bool {
+        self.system.unlink(a, b, relation)
+    }
+    pub fn adopt(&mut self, frame: FrameId, object: Option<Box<dyn Object>>) -> bool {
+        self.system.adopt(frame, object)
+    }
+    pub fn swap(&mut self, a: FrameId, b: FrameId) -> bool {
+        if !self.system.holds_object(a) || !self.system.holds_object(b) {
+            return false;
+        }
+        self.system.exchange(a, b, self.task_loop);
+        self.system.record(Edit::Swap(a, b));
+        self.swapped.extend([a, b]);
+        return true;
+    }
+    pub fn rename(&mut self, frame: FrameId, name: &str) -> Result<(), Error> {
+        self.system.rename(frame, name)
+    }
+}
+
+impl System {
+    // Applies the edits made by `edit` as a whole. If `edit` fails, or leaves
+    // a link that doesn't resolve, every edit is rolled back and the error is
+    // returned. Links that didn't resolve before don't count. Otherwise the
+    // edits are undone and redone as one.
+    pub fn transaction<F, T>(&mut self, task_loop: &mut TaskLoop, edit: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Transaction) -> Result<T, Error>,
+    {
+        let mark = self.history.len();
+        let broken = self.broken_links();
+        let undone = mem::take(&mut self.undone);
+        let mut transaction = Transaction {
+            system: self,
+            task_loop,
+            swapped: Vec::new(),
+        };
+        let result = edit(&mut transaction);
+        let swapped = transaction.swapped;
+        for frame in swapped {
+            self.fix_links(frame, task_loop);
+        }
+        let result = result.and_then(|value| self.check_links(broken).map(|()| value));
+        if result.is_err() {
+            while self.history.len() > mark {
+                let edit = self.history.pop().unwrap();
+                self.revert(edit, task_loop);
+            }
+            self.undone = undone;
+            return result;
+        }
+        if self.history.len() == mark {
+            self.undone = undone;
+        } else {
+            let edits = self.history.split_off(mark);
+            self.history.push(Edit::Batch(edits));
+        }
+        return result;
+    }
+}
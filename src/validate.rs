@@ -0,0 +1,192 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::{Error, FrameId, LinkEnd, Relation, System};
+
+// Problem found by `System::validate`. Frames are given by their paths from
+// the validated system.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Diagnostic {
+    // A link end refers to a frame that was removed.
+    DanglingLink(FrameId),
+    // A link end refers to a path that can't be found, given after the path
+    // of the frame holding the object it leads into.
+    UnresolvedLink(String),
+    // A frame without an object is the target of a `Then`, `Catch` or
+    // `After` link.
+    EmptyTarget(String),
+    // The object of a `Then` target can't run. For systems, this means they
+    // have no entry frames.
+    NotRunnable(String),
+    // Frames running each other through `Then` links, with no link leading
+    // out of the cycle.
+    ClosedCycle(Vec<String>),
+    // More than one frame of a system has this name.
+    DuplicateName(String),
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Diagnostic::DanglingLink(frame) => write!(f, "link to removed frame {:?}", frame),
+            Diagnostic::UnresolvedLink(ref path) => {
+                write!(f, "link to {} can't be resolved", path)
+            }
+            Diagnostic::EmptyTarget(ref path) => write!(f, "link target {} has no object", path),
+            Diagnostic::NotRunnable(ref path) => write!(f, "then target {} can't run", path),
+            Diagnostic::ClosedCycle(ref paths) => {
+                write!(f, "then cycle without exit: {}", paths.join(", "))
+            }
+            Diagnostic::DuplicateName(ref path) => write!(f, "frame name {} is not unique", path),
+        }
+    }
+}
+
+impl System {
+    // Lints this system and its subsystems. Meant for checking saved graphs
+    // before running them.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut links = Vec::new();
+        self.check(self, &mut links, &mut diagnostics);
+        let path = |frame: FrameId| self.element_path(frame).unwrap_or_default().join("/");
+        let mut targets: Vec<FrameId> = Vec::new();
+        let mut then_targets: Vec<FrameId> = Vec::new();
+        for &(_, b, relation) in links.iter() {
+            if relation != Relation::Arg && !targets.contains(&b) {
+                targets.push(b);
+            }
+            if relation == Relation::Then && !then_targets.contains(&b) {
+                then_targets.push(b);
+            }
+        }
+        for &target in targets.iter() {
+            if self.get(target).is_some_and(|frame| frame.object.is_none()) {
+                diagnostics.push(Diagnostic::EmptyTarget(path(target)));
+            }
+        }
+        for &target in then_targets.iter() {
+            let object = self.get(target).and_then(|frame| frame.object());
+            let runnable = object.is_some_and(|object| {
+                object.can_run() || object.system().is_some_and(|system| !system.entries.is_empty())
+            });
+            if object.is_some() && !runnable {
+                diagnostics.push(Diagnostic::NotRunnable(path(target)));
+            }
+        }
+        let mut visited: HashSet<FrameId> = HashSet::new();
+        for &frame in then_targets.iter() {
+            if visited.contains(&frame) {
+                continue;
+            }
+            let reached = reachable(&links, frame);
+            if !reached.contains(&frame) {
+                continue;
+            }
+            let cycle: Vec<FrameId> = then_targets
+                .iter()
+                .copied()
+                .filter(|other| reached.contains(other))
+                .filter(|&other| reachable(&links, other).contains(&frame))
+                .collect();
+            visited.extend(cycle.iter().copied());
+            let exit = links.iter().any(|&(a, b, relation)| {
+                relation != Relation::Arg && cycle.contains(&a) && !cycle.contains(&b)
+            });
+            if !exit {
+                diagnostics.push(Diagnostic::ClosedCycle(cycle.into_iter().map(path).collect()));
+            }
+        }
+        return diagnostics;
+    }
+    // Links of this system or its subsystems that don't resolve.
+    pub(crate) fn broken_links(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        self.check(self, &mut Vec::new(), &mut diagnostics);
+        diagnostics.retain(|diagnostic| {
+            matches!(diagnostic, Diagnostic::DanglingLink(_) | Diagnostic::UnresolvedLink(_))
+        });
+        return diagnostics;
+    }
+    // First link that doesn't resolve, other than those in `broken`.
+    pub(crate) fn check_links(&self, mut broken: Vec<Diagnostic>) -> Result<(), Error> {
+        for diagnostic in self.broken_links() {
+            if let Some(position) = broken.iter().position(|other| *other == diagnostic) {
+                broken.remove(position);
+                continue;
+            }
+            match diagnostic {
+                Diagnostic::DanglingLink(frame) => return Err(Error::DanglingFrame(frame)),
+                Diagnostic::UnresolvedLink(path) => return Err(Error::ElementNotFound(path)),
+                _ => {}
+            }
+        }
+        return Ok(());
+    }
+    // Collects the links that resolve and reports those that don't, along
+    // with duplicate names. `root` is the system being validated.
+    fn check(
+        &self,
+        root: &System,
+        links: &mut Vec<(FrameId, FrameId, Relation)>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        let mut names: Vec<&str> = Vec::new();
+        for frame in self.frames.iter().flatten() {
+            if names.contains(&frame.name.as_str()) {
+                let path = root.element_path(frame.id).unwrap_or_default().join("/");
+                let duplicate = Diagnostic::DuplicateName(path);
+                if !diagnostics.contains(&duplicate) {
+                    diagnostics.push(duplicate);
+                }
+            }
+            names.push(&frame.name);
+        }
+        for link in self.links.iter() {
+            let mut resolved = true;
+            for end in [&link.a, &link.b] {
+                let problem = match *end {
+                    LinkEnd::Frame(frame) if self.get(frame).is_none() => {
+                        Some(Diagnostic::DanglingLink(frame))
+                    }
+                    LinkEnd::FrameElement(holder, ref path) if end.resolve(self).is_none() => {
+                        match root.element_path(holder) {
+                            Some(mut holder) => {
+                                holder.extend(path.iter().cloned());
+                                Some(Diagnostic::UnresolvedLink(holder.join("/")))
+                            }
+                            None => Some(Diagnostic::DanglingLink(holder)),
+                        }
+                    }
+                    _ => None,
+                };
+                if let Some(problem) = problem {
+                    diagnostics.push(problem);
+                    resolved = false;
+                }
+            }
+            if let (true, Some(a), Some(b)) = (resolved, link.a.resolve(self), link.b.resolve(self)) {
+                links.push((a, b, link.relation));
+            }
+        }
+        for frame in self.frames.iter().flatten() {
+            if let Some(system) = frame.object().and_then(|object| object.system()) {
+                system.check(root, links, diagnostics);
+            }
+        }
+    }
+}
+
+// Frames run after `frame` through `Then` links, directly or not.
+fn reachable(links: &[(FrameId, FrameId, Relation)], frame: FrameId) -> HashSet<FrameId> {
+    let mut reached = HashSet::new();
+    let mut stack = vec![frame];
+    while let Some(frame) = stack.pop() {
+        for &(a, b, relation) in links.iter() {
+            if a == frame && relation == Relation::Then && reached.insert(b) {
+                stack.push(b);
+            }
+        }
+    }
+    return reached;
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::{run_object, CancelToken, Error, Object, Outcome, RunContext, TaskEvent, Value};
+
+// Everything a worker needs to run an object away from its frame.
+pub struct Job {
+    pub id: u64,
+    pub object: Box<dyn Object>,
+    pub args: HashMap<String, Value>,
+    pub errors: Vec<Error>,
+    pub tx: Sender<(u64, TaskEvent)>,
+    pub token: CancelToken,
+    pub restart: bool,
+}
+
+pub struct WorkerPool {
+    jobs: Option<Sender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn new(count: usize) -> WorkerPool {
+        let (jobs, rx) = mpsc::channel();
+        let rx = Arc::new(Mutex::new(rx));
+        let threads = (0..count)
+            .map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || work(&rx))
+            })
+            .collect();
+        WorkerPool {
+            jobs: Some(jobs),
+            threads,
+        }
+    }
+    pub fn size(&self) -> usize {
+        self.threads.len()
+    }
+    pub fn run(&self, job: Job) {
+        self.jobs.as_ref().unwrap().send(job).unwrap();
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.jobs.take();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Runs jobs until the pool goes away. Objects travel back to the loop
+// together with the outcome of their run, even if they panicked.
+fn work(rx: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = rx.lock().unwrap().recv();
+        let Job {
+            id,
+            mut object,
+            args,
+            errors,
+            tx,
+            token,
+            restart,
+        } = match job {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let mut outcome = None;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            run_object(&mut *object, RunContext {
+                id,
+                tx: tx.clone(),
+                outcome: &mut outcome,
+                args,
+                errors,
+                token,
+                restart,
+                updaters: Arc::new(()),
+            });
+        }));
+        let outcome = match result {
+            Ok(()) => outcome.unwrap_or(Outcome::Finished(None)),
+            Err(payload) => Outcome::Panicked(payload),
+        };
+        let _ = tx.send((id, TaskEvent::Ran(object, outcome)));
+    }
+}